@@ -0,0 +1,68 @@
+//! Pure-Rust gain stage used when the webrtc `Processor` cannot be created:
+//! peak normalization towards a target level followed by a soft-knee limiter.
+
+/// Level the input peaks are normalized to.
+const TARGET_PEAK_DBFS: f32 = -3.0;
+/// Same ceiling as the webrtc AGC's `compression_gain_db`.
+const MAX_GAIN_DB: f32 = 15.0;
+/// Below this peak the input is treated as silence and the gain is held,
+/// so pauses don't get boosted into audible noise.
+const SILENCE_PEAK_DBFS: f32 = -50.0;
+/// Per-10ms-frame decay of the peak estimate (~1s time constant).
+const PEAK_DECAY: f32 = 0.99;
+/// Per-frame smoothing of the gain: cuts land within a few frames, boosts take ~0.5s.
+const GAIN_ATTACK: f32 = 0.5;
+const GAIN_RELEASE: f32 = 0.02;
+/// The limiter is linear up to the knee and then bends smoothly towards the ceiling.
+const LIMITER_KNEE_DBFS: f32 = -6.0;
+const LIMITER_CEILING_DBFS: f32 = -1.0;
+
+fn db_to_amp(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+/// Linear below the knee; above it a tanh curve with slope 1 at the knee that
+/// never exceeds the ceiling.
+fn soft_limit(x: f32) -> f32 {
+    let knee = db_to_amp(LIMITER_KNEE_DBFS);
+    let range = db_to_amp(LIMITER_CEILING_DBFS) - knee;
+    let magnitude = x.abs();
+    if magnitude <= knee {
+        return x;
+    }
+    (knee + range * ((magnitude - knee) / range).tanh()).copysign(x)
+}
+
+pub struct SoftwareAgc {
+    peak: f32,
+    gain: f32,
+}
+
+impl SoftwareAgc {
+    pub fn new() -> Self {
+        Self { peak: 0.0, gain: 1.0 }
+    }
+
+    /// Applies the gain and limiter in place. Interleaved channels share one
+    /// gain so the stereo image doesn't shift.
+    pub fn process(&mut self, frame: &mut [f32]) {
+        let frame_peak = frame.iter().fold(0.0f32, |m, s| m.max(s.abs()));
+        self.peak = frame_peak.max(self.peak * PEAK_DECAY);
+
+        let target_gain = if self.peak < db_to_amp(SILENCE_PEAK_DBFS) {
+            self.gain
+        } else {
+            (db_to_amp(TARGET_PEAK_DBFS) / self.peak).min(db_to_amp(MAX_GAIN_DB))
+        };
+        let smoothing = if target_gain < self.gain { GAIN_ATTACK } else { GAIN_RELEASE };
+        let new_gain = self.gain + (target_gain - self.gain) * smoothing;
+
+        // Ramp across the frame so gain changes don't step audibly.
+        let step = (new_gain - self.gain) / frame.len().max(1) as f32;
+        for s in frame.iter_mut() {
+            self.gain += step;
+            *s = soft_limit(*s * self.gain);
+        }
+        self.gain = new_gain;
+    }
+}
@@ -0,0 +1,72 @@
+/// Direct form I biquad section with normalized (a0 = 1) coefficients.
+#[derive(Clone, Copy, Debug)]
+pub struct Biquad {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+    x1: f64,
+    x2: f64,
+    y1: f64,
+    y2: f64,
+}
+
+impl Biquad {
+    pub fn new(b0: f64, b1: f64, b2: f64, a1: f64, a2: f64) -> Self {
+        Self { b0, b1, b2, a1, a2, x1: 0.0, x2: 0.0, y1: 0.0, y2: 0.0 }
+    }
+
+    /// RBJ cookbook low shelf (slope 1); `gain_db / 2` at `freq`.
+    pub fn low_shelf(sample_rate: f64, freq: f64, gain_db: f64) -> Self {
+        let (a, cos, alpha) = Self::cookbook_terms(sample_rate, freq, gain_db, std::f64::consts::FRAC_1_SQRT_2);
+        let k = 2.0 * a.sqrt() * alpha;
+        Self::normalized(
+            a * ((a + 1.0) - (a - 1.0) * cos + k),
+            2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+            a * ((a + 1.0) - (a - 1.0) * cos - k),
+            (a + 1.0) + (a - 1.0) * cos + k,
+            -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+            (a + 1.0) + (a - 1.0) * cos - k,
+        )
+    }
+
+    /// RBJ cookbook high shelf (slope 1); `gain_db / 2` at `freq`.
+    pub fn high_shelf(sample_rate: f64, freq: f64, gain_db: f64) -> Self {
+        let (a, cos, alpha) = Self::cookbook_terms(sample_rate, freq, gain_db, std::f64::consts::FRAC_1_SQRT_2);
+        let k = 2.0 * a.sqrt() * alpha;
+        Self::normalized(
+            a * ((a + 1.0) + (a - 1.0) * cos + k),
+            -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+            a * ((a + 1.0) + (a - 1.0) * cos - k),
+            (a + 1.0) - (a - 1.0) * cos + k,
+            2.0 * ((a - 1.0) - (a + 1.0) * cos),
+            (a + 1.0) - (a - 1.0) * cos - k,
+        )
+    }
+
+    /// RBJ cookbook peaking EQ; exactly `gain_db` at `freq`.
+    pub fn peaking(sample_rate: f64, freq: f64, gain_db: f64, q: f64) -> Self {
+        let (a, cos, alpha) = Self::cookbook_terms(sample_rate, freq, gain_db, q);
+        Self::normalized(1.0 + alpha * a, -2.0 * cos, 1.0 - alpha * a, 1.0 + alpha / a, -2.0 * cos, 1.0 - alpha / a)
+    }
+
+    /// `(A, cos(w0), alpha)` as defined in the cookbook, with alpha derived from `q`.
+    fn cookbook_terms(sample_rate: f64, freq: f64, gain_db: f64, q: f64) -> (f64, f64, f64) {
+        let w0 = 2.0 * std::f64::consts::PI * freq / sample_rate;
+        (10f64.powf(gain_db / 40.0), w0.cos(), w0.sin() / (2.0 * q))
+    }
+
+    fn normalized(b0: f64, b1: f64, b2: f64, a0: f64, a1: f64, a2: f64) -> Self {
+        Self::new(b0 / a0, b1 / a0, b2 / a0, a1 / a0, a2 / a0)
+    }
+
+    pub fn process(&mut self, x: f64) -> f64 {
+        let y = self.b0 * x + self.b1 * self.x1 + self.b2 * self.x2 - self.a1 * self.y1 - self.a2 * self.y2;
+        self.x2 = self.x1;
+        self.x1 = x;
+        self.y2 = self.y1;
+        self.y1 = y;
+        y
+    }
+}
@@ -0,0 +1,176 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Why a LiveKit URL/token pair would be rejected, checked before connecting.
+#[derive(Debug)]
+pub enum CredentialError {
+    InvalidUrl(String),
+    MalformedToken(String),
+    Expired { secs_ago: u64 },
+    NotYetValid { secs_until: u64 },
+    MissingGrant(&'static str),
+}
+
+impl std::fmt::Display for CredentialError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CredentialError::InvalidUrl(url) => write!(f, "LiveKit URL must start with ws:// or wss://, got '{}'", url),
+            CredentialError::MalformedToken(why) => write!(f, "Malformed access token: {}", why),
+            CredentialError::Expired { secs_ago } => write!(f, "Access token expired {} ago", human_duration(*secs_ago)),
+            CredentialError::NotYetValid { secs_until } => write!(f, "Access token is not valid for another {}", human_duration(*secs_until)),
+            CredentialError::MissingGrant(grant) => write!(f, "Access token is missing the '{}' grant", grant),
+        }
+    }
+}
+
+impl std::error::Error for CredentialError {}
+
+fn human_duration(secs: u64) -> String {
+    let (n, unit) = match secs {
+        s if s >= 3600 => (s / 3600, "hour"),
+        s if s >= 60 => (s / 60, "minute"),
+        s => (s, "second"),
+    };
+    format!("{} {}{}", n, unit, if n == 1 { "" } else { "s" })
+}
+
+/// Checks the URL scheme and decodes (without verifying the signature) the JWT
+/// claims, so an expired token or missing grant is reported before `Room::connect`.
+pub fn validate_livekit_credentials(url: &str, token: &str) -> Result<(), CredentialError> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    validate_at(url, token, now)
+}
+
+/// `validate_livekit_credentials` against a given Unix time.
+fn validate_at(url: &str, token: &str, now: u64) -> Result<(), CredentialError> {
+    if !(url.starts_with("ws://") || url.starts_with("wss://")) {
+        return Err(CredentialError::InvalidUrl(url.to_string()));
+    }
+
+    let claims = decode_claims(token)?;
+
+    match claims.get("exp").and_then(|v| v.as_u64()) {
+        Some(exp) if exp <= now => return Err(CredentialError::Expired { secs_ago: now - exp }),
+        Some(_) => {}
+        None => return Err(CredentialError::MalformedToken("no 'exp' claim".to_string())),
+    }
+    if let Some(nbf) = claims.get("nbf").and_then(|v| v.as_u64()) {
+        if nbf > now {
+            return Err(CredentialError::NotYetValid { secs_until: nbf - now });
+        }
+    }
+
+    let video = claims.get("video").ok_or(CredentialError::MissingGrant("video"))?;
+    if video.get("roomJoin").and_then(|v| v.as_bool()) != Some(true) {
+        return Err(CredentialError::MissingGrant("roomJoin"));
+    }
+    // LiveKit treats an absent canPublish as allowed.
+    if video.get("canPublish").and_then(|v| v.as_bool()) == Some(false) {
+        return Err(CredentialError::MissingGrant("canPublish"));
+    }
+    // An empty/absent source list means every source is allowed.
+    if let Some(sources) = video.get("canPublishSources").and_then(|v| v.as_array()) {
+        if !sources.is_empty() && !sources.iter().any(|s| s.as_str() == Some("microphone")) {
+            return Err(CredentialError::MissingGrant("canPublishSources: microphone"));
+        }
+    }
+    Ok(())
+}
+
+fn decode_claims(token: &str) -> Result<serde_json::Value, CredentialError> {
+    let mut parts = token.split('.');
+    let payload = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(_), Some(payload), Some(_), None) => payload,
+        _ => return Err(CredentialError::MalformedToken("expected three '.'-separated parts".to_string())),
+    };
+    let bytes = URL_SAFE_NO_PAD
+        .decode(payload.trim_end_matches('='))
+        .map_err(|e| CredentialError::MalformedToken(format!("payload is not base64url: {}", e)))?;
+    serde_json::from_slice(&bytes).map_err(|e| CredentialError::MalformedToken(format!("payload is not JSON: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_700_000_000;
+    const URL: &str = "wss://example.livekit.cloud";
+
+    fn token(claims: &serde_json::Value) -> String {
+        format!("eyJhbGciOiJIUzI1NiJ9.{}.c2ln", URL_SAFE_NO_PAD.encode(claims.to_string()))
+    }
+
+    fn valid_claims() -> serde_json::Value {
+        serde_json::json!({ "exp": NOW + 600, "nbf": NOW - 10, "video": { "roomJoin": true, "room": "r" } })
+    }
+
+    #[test]
+    fn accepts_a_valid_token() {
+        assert!(validate_at(URL, &token(&valid_claims()), NOW).is_ok());
+        assert!(validate_at("ws://localhost:7880", &token(&valid_claims()), NOW).is_ok());
+    }
+
+    #[test]
+    fn rejects_non_websocket_urls() {
+        let err = validate_at("https://example.livekit.cloud", &token(&valid_claims()), NOW).unwrap_err();
+        assert!(matches!(err, CredentialError::InvalidUrl(_)));
+    }
+
+    #[test]
+    fn rejects_malformed_tokens() {
+        for bad in ["", "abc", "a.b", "a.b.c.d", "a.!!!.c", &format!("a.{}.c", URL_SAFE_NO_PAD.encode("not json"))] {
+            assert!(matches!(validate_at(URL, bad, NOW), Err(CredentialError::MalformedToken(_))), "{}", bad);
+        }
+        let mut claims = valid_claims();
+        claims.as_object_mut().unwrap().remove("exp");
+        assert!(matches!(validate_at(URL, &token(&claims), NOW), Err(CredentialError::MalformedToken(_))));
+    }
+
+    #[test]
+    fn reports_how_long_ago_the_token_expired() {
+        let mut claims = valid_claims();
+        claims["exp"] = (NOW - 2 * 3600).into();
+        let err = validate_at(URL, &token(&claims), NOW).unwrap_err();
+        assert!(matches!(err, CredentialError::Expired { secs_ago: 7200 }));
+        assert_eq!(err.to_string(), "Access token expired 2 hours ago");
+    }
+
+    #[test]
+    fn rejects_tokens_not_yet_valid() {
+        let mut claims = valid_claims();
+        claims["nbf"] = (NOW + 60).into();
+        let err = validate_at(URL, &token(&claims), NOW).unwrap_err();
+        assert_eq!(err.to_string(), "Access token is not valid for another 1 minute");
+    }
+
+    #[test]
+    fn requires_join_and_publish_grants() {
+        let mut claims = valid_claims();
+        claims["video"]["roomJoin"] = false.into();
+        assert!(matches!(validate_at(URL, &token(&claims), NOW), Err(CredentialError::MissingGrant("roomJoin"))));
+
+        let mut claims = valid_claims();
+        claims["video"]["canPublish"] = false.into();
+        assert!(matches!(validate_at(URL, &token(&claims), NOW), Err(CredentialError::MissingGrant("canPublish"))));
+
+        let mut claims = valid_claims();
+        claims["video"]["canPublishSources"] = serde_json::json!(["camera"]);
+        assert!(matches!(validate_at(URL, &token(&claims), NOW), Err(CredentialError::MissingGrant(_))));
+        claims["video"]["canPublishSources"] = serde_json::json!(["camera", "microphone"]);
+        assert!(validate_at(URL, &token(&claims), NOW).is_ok());
+
+        let mut claims = valid_claims();
+        claims.as_object_mut().unwrap().remove("video");
+        assert!(matches!(validate_at(URL, &token(&claims), NOW), Err(CredentialError::MissingGrant("video"))));
+    }
+
+    #[test]
+    fn durations_are_pluralized() {
+        assert_eq!(human_duration(1), "1 second");
+        assert_eq!(human_duration(59), "59 seconds");
+        assert_eq!(human_duration(60), "1 minute");
+        assert_eq!(human_duration(3600), "1 hour");
+        assert_eq!(human_duration(3 * 3600 + 5), "3 hours");
+    }
+}
@@ -0,0 +1,152 @@
+use nnnoiseless::DenoiseState;
+use std::sync::Arc;
+
+use crate::FRAME_SIZE;
+
+/// A noise-suppression stage operating in place on one 48kHz mono frame.
+pub trait Denoiser: Send {
+    fn process(&mut self, frame: &mut [f32; FRAME_SIZE]);
+}
+
+/// Builds a fresh denoiser for each session (denoisers carry per-stream state).
+pub type DenoiserFactory = Arc<dyn Fn() -> Box<dyn Denoiser> + Send + Sync>;
+
+pub struct Passthrough;
+
+impl Denoiser for Passthrough {
+    fn process(&mut self, _frame: &mut [f32; FRAME_SIZE]) {}
+}
+
+pub struct RnNoise {
+    state: Box<DenoiseState<'static>>,
+    input: [f32; FRAME_SIZE],
+}
+
+impl RnNoise {
+    pub fn new() -> Self {
+        Self { state: DenoiseState::new(), input: [0.0; FRAME_SIZE] }
+    }
+}
+
+impl Denoiser for RnNoise {
+    /// RNNoise expects samples in i16 range, so the ±1.0 frame is scaled up and back.
+    fn process(&mut self, frame: &mut [f32; FRAME_SIZE]) {
+        for (d, &s) in self.input.iter_mut().zip(frame.iter()) {
+            *d = s * i16::MAX as f32;
+        }
+        self.state.process_frame(frame, &self.input);
+        frame.iter_mut().for_each(|s| *s /= i16::MAX as f32);
+    }
+}
+
+/// Runs each denoiser in order.
+pub struct Chain(pub Vec<Box<dyn Denoiser>>);
+
+impl Denoiser for Chain {
+    fn process(&mut self, frame: &mut [f32; FRAME_SIZE]) {
+        for d in self.0.iter_mut() {
+            d.process(frame);
+        }
+    }
+}
+
+/// The stock stage after the webrtc `Processor`. webrtc noise suppression runs
+/// inside that processor (see `AudioSettings::noise_suppression_level`) when
+/// `webrtc_ns` is set, optionally followed by an extra RNNoise pass (which can
+/// sound robotic on an already clean mic). Without webrtc NS RNNoise always runs.
+pub fn default_denoiser(extra_rnnoise: bool, webrtc_ns: bool) -> Box<dyn Denoiser> {
+    let mut stages: Vec<Box<dyn Denoiser>> = Vec::new();
+    if extra_rnnoise || !webrtc_ns {
+        stages.push(Box::new(RnNoise::new()));
+    }
+    Box::new(Chain(stages))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Add(f32);
+
+    impl Denoiser for Add {
+        fn process(&mut self, frame: &mut [f32; FRAME_SIZE]) {
+            frame.iter_mut().for_each(|s| *s += self.0);
+        }
+    }
+
+    struct Scale(f32);
+
+    impl Denoiser for Scale {
+        fn process(&mut self, frame: &mut [f32; FRAME_SIZE]) {
+            frame.iter_mut().for_each(|s| *s *= self.0);
+        }
+    }
+
+    fn ramp() -> [f32; FRAME_SIZE] {
+        std::array::from_fn(|i| i as f32 / FRAME_SIZE as f32 - 0.5)
+    }
+
+    #[test]
+    fn passthrough_leaves_samples_unchanged() {
+        let mut frame = ramp();
+        Passthrough.process(&mut frame);
+        assert_eq!(frame, ramp());
+    }
+
+    #[test]
+    fn chain_applies_stages_in_order() {
+        let mut frame = [1.0f32; FRAME_SIZE];
+        Chain(vec![Box::new(Add(1.0)), Box::new(Scale(3.0))]).process(&mut frame);
+        assert!(frame.iter().all(|&s| s == 6.0));
+
+        let mut frame = [1.0f32; FRAME_SIZE];
+        Chain(vec![Box::new(Scale(3.0)), Box::new(Add(1.0))]).process(&mut frame);
+        assert!(frame.iter().all(|&s| s == 4.0));
+    }
+
+    /// The ramp spanning the full ±1.0 range the pipeline uses.
+    fn full_scale_ramp() -> [f32; FRAME_SIZE] {
+        ramp().map(|s| s * 2.0)
+    }
+
+    #[test]
+    fn extra_rnnoise_pass_is_bypassed_when_disabled() {
+        let mut frame = full_scale_ramp();
+        default_denoiser(false, true).process(&mut frame);
+        assert_eq!(frame, full_scale_ramp());
+
+        let mut frame = full_scale_ramp();
+        default_denoiser(true, true).process(&mut frame);
+        assert_ne!(frame, full_scale_ramp());
+    }
+
+    #[test]
+    fn rnnoise_runs_without_webrtc_ns() {
+        let mut frame = full_scale_ramp();
+        default_denoiser(false, false).process(&mut frame);
+        assert_ne!(frame, full_scale_ramp());
+    }
+
+    #[test]
+    fn rnnoise_removes_a_quiet_hiss() {
+        // Three seconds of white noise around -65dBFS in the pipeline's ±1.0 range;
+        // read as i16-range samples it would be inaudible and pass through untouched.
+        let mut seed = 1u32;
+        let hiss: Vec<f32> = (0..FRAME_SIZE * 300)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                (seed >> 8) as f32 / (1 << 24) as f32 * 0.002 - 0.001
+            })
+            .collect();
+        let mut rnnoise = RnNoise::new();
+        let mut out = hiss.clone();
+        for frame in out.chunks_exact_mut(FRAME_SIZE) {
+            rnnoise.process(frame.try_into().unwrap());
+        }
+
+        // Past the first second the noise estimate has adapted.
+        let energy = |s: &[f32]| s[FRAME_SIZE * 100..].iter().map(|s| s * s).sum::<f32>();
+        let reduction_db = 10.0 * (energy(&out) / energy(&hiss)).log10();
+        assert!(reduction_db < -10.0, "{:.1}dB", reduction_db);
+    }
+}
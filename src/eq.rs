@@ -0,0 +1,158 @@
+use crate::biquad::Biquad;
+
+const SAMPLE_RATE: f64 = 48000.0;
+/// Largest boost or cut per band.
+const MAX_GAIN_DB: f32 = 24.0;
+
+/// A 3-band mic EQ: low shelf, mid peak, high shelf. Gains are in dB; 0 leaves a band flat.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EqSettings {
+    pub low_freq_hz: f32,
+    pub low_gain_db: f32,
+    pub mid_freq_hz: f32,
+    pub mid_gain_db: f32,
+    pub mid_q: f32,
+    pub high_freq_hz: f32,
+    pub high_gain_db: f32,
+}
+
+impl Default for EqSettings {
+    fn default() -> Self {
+        Self {
+            low_freq_hz: 120.0,
+            low_gain_db: 0.0,
+            mid_freq_hz: 1000.0,
+            mid_gain_db: 0.0,
+            mid_q: 0.7,
+            high_freq_hz: 6000.0,
+            high_gain_db: 0.0,
+        }
+    }
+}
+
+/// Why an `EqSettings` was rejected: the biquads would produce NaN or go unstable.
+#[derive(Debug)]
+pub enum EqError {
+    /// Band frequencies must lie strictly between 0Hz and Nyquist (24kHz).
+    Frequency { band: &'static str, hz: f32 },
+    Gain { band: &'static str, db: f32 },
+    /// The mid band's Q must be positive.
+    Q(f32),
+}
+
+impl std::fmt::Display for EqError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EqError::Frequency { band, hz } => write!(f, "EQ {} frequency must be between 0 and {}Hz, got {}", band, SAMPLE_RATE / 2.0, hz),
+            EqError::Gain { band, db } => write!(f, "EQ {} gain must be within ±{}dB, got {}", band, MAX_GAIN_DB, db),
+            EqError::Q(q) => write!(f, "EQ mid Q must be positive, got {}", q),
+        }
+    }
+}
+
+impl std::error::Error for EqError {}
+
+impl EqSettings {
+    pub fn validate(&self) -> Result<(), EqError> {
+        let nyquist = (SAMPLE_RATE / 2.0) as f32;
+        for (band, hz, db) in [
+            ("low", self.low_freq_hz, self.low_gain_db),
+            ("mid", self.mid_freq_hz, self.mid_gain_db),
+            ("high", self.high_freq_hz, self.high_gain_db),
+        ] {
+            // Written so NaN fails too.
+            if !(hz > 0.0 && hz < nyquist) {
+                return Err(EqError::Frequency { band, hz });
+            }
+            if db.is_nan() || db.abs() > MAX_GAIN_DB {
+                return Err(EqError::Gain { band, db });
+            }
+        }
+        if !(self.mid_q > 0.0 && self.mid_q.is_finite()) {
+            return Err(EqError::Q(self.mid_q));
+        }
+        Ok(())
+    }
+}
+
+/// The cascaded biquads for an `EqSettings`, running on one channel of the 48kHz mic signal.
+pub struct InputEq {
+    bands: [Biquad; 3],
+}
+
+impl InputEq {
+    pub fn new(settings: &EqSettings) -> Self {
+        Self {
+            bands: [
+                Biquad::low_shelf(SAMPLE_RATE, settings.low_freq_hz as f64, settings.low_gain_db as f64),
+                Biquad::peaking(SAMPLE_RATE, settings.mid_freq_hz as f64, settings.mid_gain_db as f64, settings.mid_q as f64),
+                Biquad::high_shelf(SAMPLE_RATE, settings.high_freq_hz as f64, settings.high_gain_db as f64),
+            ],
+        }
+    }
+
+    /// Filters one channel's samples in order; interleaved audio passes a strided iterator.
+    pub fn process<'a>(&mut self, samples: impl Iterator<Item = &'a mut f32>) {
+        for s in samples {
+            let mut y = *s as f64;
+            for band in self.bands.iter_mut() {
+                y = band.process(y);
+            }
+            *s = y as f32;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Steady-state gain in dB of `eq` for a sine at `freq`.
+    fn response_db(eq: &EqSettings, freq: f64) -> f64 {
+        let mut filter = InputEq::new(eq);
+        let mut samples: Vec<f32> = (0..48000).map(|i| (2.0 * std::f64::consts::PI * freq * i as f64 / SAMPLE_RATE).sin() as f32).collect();
+        filter.process(samples.iter_mut());
+        // Skip the first half second so the filters have settled; the rest is a
+        // whole number of periods for every frequency tested.
+        let settled = &samples[24000..];
+        let rms = (settled.iter().map(|s| (*s as f64).powi(2)).sum::<f64>() / settled.len() as f64).sqrt();
+        20.0 * (rms * std::f64::consts::SQRT_2).log10()
+    }
+
+    fn assert_db(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 0.1, "{:.2}dB, expected {:.2}dB", actual, expected);
+    }
+
+    fn gains(low_gain_db: f32, mid_gain_db: f32, high_gain_db: f32) -> EqSettings {
+        EqSettings { low_gain_db, mid_gain_db, high_gain_db, ..EqSettings::default() }
+    }
+
+    #[test]
+    fn flat_settings_leave_the_response_flat() {
+        for freq in [50.0, 120.0, 1000.0, 6000.0, 15000.0] {
+            assert_db(response_db(&EqSettings::default(), freq), 0.0);
+        }
+    }
+
+    #[test]
+    fn bands_hit_their_gain_at_the_set_frequencies() {
+        // Shelves reach half their gain at the corner; the peak reaches all of it.
+        assert_db(response_db(&gains(6.0, 0.0, 0.0), 120.0), 3.0);
+        assert_db(response_db(&gains(0.0, -9.0, 0.0), 1000.0), -9.0);
+        assert_db(response_db(&gains(0.0, 0.0, 4.0), 6000.0), 2.0);
+        // Well inside the shelves the full gain applies.
+        assert_db(response_db(&gains(6.0, 0.0, 0.0), 16.0), 6.0);
+        assert_db(response_db(&gains(0.0, 0.0, 4.0), 20000.0), 4.0);
+    }
+
+    #[test]
+    fn unstable_settings_are_rejected() {
+        let ok = EqSettings::default();
+        assert!(ok.validate().is_ok());
+        assert!(matches!(EqSettings { low_freq_hz: 0.0, ..ok }.validate(), Err(EqError::Frequency { band: "low", .. })));
+        assert!(matches!(EqSettings { high_freq_hz: 24000.0, ..ok }.validate(), Err(EqError::Frequency { band: "high", .. })));
+        assert!(matches!(EqSettings { mid_freq_hz: f32::NAN, ..ok }.validate(), Err(EqError::Frequency { band: "mid", .. })));
+        assert!(matches!(EqSettings { mid_q: 0.0, ..ok }.validate(), Err(EqError::Q(_))));
+        assert!(matches!(gains(40.0, 0.0, 0.0).validate(), Err(EqError::Gain { band: "low", .. })));
+    }
+}
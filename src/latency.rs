@@ -0,0 +1,158 @@
+use cpal::traits::StreamTrait;
+use ringbuf::HeapRb;
+use std::sync::atomic::AtomicU64;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::{
+    build_capture_stream, build_playback_stream, resolve_input_device, resolve_output_device,
+    CaptureResampler, PlaybackResampler, ResamplerQuality, FRAME_SIZE,
+};
+
+/// Silence played before the click so both streams have settled.
+const LEAD_IN: usize = 48000 / 5;
+/// Total length of the probe signal and of the recording, at 48kHz.
+const PROBE_LEN: usize = 48000 * 3 / 2;
+/// Below this the peak is not clearly above the background and the result is unusable.
+const MIN_CONFIDENCE: f32 = 0.3;
+
+pub struct LatencyMeasurement {
+    pub delay_ms: f32,
+    /// 0..1, how far the detected click stands out from the next best match.
+    pub confidence: f32,
+}
+
+/// A 5ms Hann-windowed 1kHz burst: short enough to locate precisely, tonal
+/// enough to survive speaker/mic band-limiting.
+fn click_template() -> Vec<f32> {
+    let len = 240;
+    (0..len)
+        .map(|i| {
+            let t = i as f32 / 48000.0;
+            let window = 0.5 - 0.5 * (2.0 * std::f32::consts::PI * i as f32 / (len - 1) as f32).cos();
+            0.8 * window * (2.0 * std::f32::consts::PI * 1000.0 * t).sin()
+        })
+        .collect()
+}
+
+/// Cross-correlates `recording` with `template` and returns the best-matching
+/// offset plus a peak-to-sidelobe confidence (the sidelobe being the best match
+/// more than 5ms away from the peak).
+pub fn find_click(recording: &[f32], template: &[f32]) -> Option<(usize, f32)> {
+    if recording.len() < template.len() {
+        return None;
+    }
+    let corr: Vec<f32> = (0..=recording.len() - template.len())
+        .map(|i| recording[i..i + template.len()].iter().zip(template).map(|(a, b)| a * b).sum::<f32>().abs())
+        .collect();
+
+    let (peak_idx, &peak) = corr.iter().enumerate().max_by(|a, b| a.1.total_cmp(b.1))?;
+    if peak <= 0.0 {
+        return None;
+    }
+    let guard = 48000 / 200;
+    let sidelobe = corr
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| i.abs_diff(peak_idx) > guard)
+        .map(|(_, &c)| c)
+        .fold(0.0f32, f32::max);
+    Some((peak_idx, 1.0 - sidelobe / peak))
+}
+
+/// Plays a short click on `out_id`, records it back on `in_id` and reports the
+/// mic-to-speaker round trip. Run it with speakers (not headphones) and a quiet
+/// room; the result is meant for `AudioSettings::aec_stream_delay_ms`.
+pub fn measure_loopback_latency(in_id: &str, out_id: &str) -> anyhow::Result<LatencyMeasurement> {
+    let host = cpal::default_host();
+    let in_device = resolve_input_device(&host, in_id)?;
+    let out_device = resolve_output_device(&host, out_id)?;
+
+    let (prod_out, cons_out) = HeapRb::<f32>::new(48000 * 2).split();
+    let (in_stream, in_sr, cons_in) =
+        build_capture_stream(&in_device, 1, Arc::new(AtomicU64::new(0)), |e| println!("⚠️ Input stream error: {}", e))?;
+    let (out_stream, out_sr) =
+        build_playback_stream(&out_device, cons_out, None, 1, |e| println!("⚠️ Output stream error: {}", e))?;
+
+    let template = click_template();
+    let mut probe = vec![0.0f32; PROBE_LEN];
+    probe[LEAD_IN..LEAD_IN + template.len()].copy_from_slice(&template);
+
+    let mut playback = PlaybackResampler::new(prod_out, out_sr, ResamplerQuality::High, 1);
+    for frame in probe.chunks(FRAME_SIZE) {
+        playback.push_frame(frame.to_vec());
+    }
+    let mut capture = CaptureResampler::new(cons_in, in_sr, ResamplerQuality::High, 1);
+
+    println!("⏱️  Measuring loopback latency...");
+    in_stream.play()?;
+    out_stream.play()?;
+
+    let mut recording = Vec::with_capacity(PROBE_LEN);
+    let deadline = Instant::now() + Duration::from_secs(3);
+    while recording.len() < PROBE_LEN && Instant::now() < deadline {
+        capture.poll();
+        while let Some(frame) = capture.next_frame() {
+            recording.extend_from_slice(&frame);
+        }
+        std::thread::sleep(Duration::from_millis(5));
+    }
+
+    let (idx, confidence) = find_click(&recording, &template)
+        .ok_or_else(|| anyhow::anyhow!("No click detected in the recording"))?;
+    if idx < LEAD_IN {
+        return Err(anyhow::anyhow!("Click detected before it was played; is another source feeding the mic?"));
+    }
+    let delay_ms = (idx - LEAD_IN) as f32 / 48.0;
+    println!("⏱️  Loopback latency: {:.1}ms (confidence {:.0}%)", delay_ms, confidence * 100.0);
+
+    if confidence < MIN_CONFIDENCE {
+        return Err(anyhow::anyhow!("Click not clearly detected (confidence {:.0}%); raise the volume or reduce background noise", confidence * 100.0));
+    }
+    Ok(LatencyMeasurement { delay_ms, confidence })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deterministic low-level noise standing in for a room's background.
+    fn noise(len: usize) -> Vec<f32> {
+        let mut seed = 0x2545_f491u32;
+        (0..len)
+            .map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 17;
+                seed ^= seed << 5;
+                (seed as f32 / u32::MAX as f32 - 0.5) * 0.02
+            })
+            .collect()
+    }
+
+    #[test]
+    fn finds_click_at_known_offset() {
+        let template = click_template();
+        let offset = 12_345;
+        let mut recording = noise(PROBE_LEN);
+        for (r, t) in recording[offset..].iter_mut().zip(&template) {
+            // Quieter than played, as after a speaker/mic round trip.
+            *r += 0.25 * t;
+        }
+        let (idx, confidence) = find_click(&recording, &template).unwrap();
+        assert_eq!(idx, offset);
+        assert!(confidence > MIN_CONFIDENCE, "confidence {}", confidence);
+    }
+
+    #[test]
+    fn noise_alone_is_low_confidence() {
+        let template = click_template();
+        let (_, confidence) = find_click(&noise(PROBE_LEN), &template).unwrap();
+        assert!(confidence < MIN_CONFIDENCE, "confidence {}", confidence);
+    }
+
+    #[test]
+    fn recording_shorter_than_the_click_finds_nothing() {
+        let template = click_template();
+        assert!(find_click(&template[..100], &template).is_none());
+    }
+}
@@ -0,0 +1,96 @@
+//! "What you hear" capture on Windows: WASAPI loopback of the default render endpoint.
+
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use ringbuf::HeapRb;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::Duration;
+
+use crate::{build_f32_input_stream, CaptureResampler, ResamplerQuality, CAPTURE_BUFFER_SECS, FRAME_SIZE};
+
+/// Mixer input id that selects the loopback instead of a capture device.
+pub const SYSTEM_AUDIO_INPUT_ID: &str = "system-audio";
+
+pub struct SystemAudioLoopback {
+    stream: cpal::Stream,
+    /// Cleared on drop or when the endpoint goes away; the resampling thread then exits.
+    running: Arc<AtomicBool>,
+    thread: Option<std::thread::JoinHandle<()>>,
+    /// 48kHz samples dropped because the receiver fell `CAPTURE_BUFFER_SECS` behind.
+    dropped: Arc<AtomicU64>,
+}
+
+impl SystemAudioLoopback {
+    /// Starts loopback capture and returns 48kHz mono frames of `FRAME_SIZE`
+    /// samples, the same contract as the mic path, ready for mixing/publishing.
+    /// The receiver disconnects when the loopback is dropped or the render
+    /// endpoint disappears; the resampling thread also exits once the receiver
+    /// is dropped. At most `CAPTURE_BUFFER_SECS` of frames are queued; newer
+    /// frames are dropped and counted in `dropped`.
+    pub fn start() -> anyhow::Result<(Self, mpsc::Receiver<Vec<f32>>)> {
+        let host = cpal::host_from_id(cpal::HostId::Wasapi)?;
+        let device = host.default_output_device().ok_or_else(|| anyhow::anyhow!("No speaker found"))?;
+        // WASAPI opens an input stream on a render device in loopback mode.
+        let config = device.default_output_config()?;
+        let sr = config.sample_rate().0 as f64;
+
+        println!("🔊 Opening loopback: {} ({}Hz, {:?})", device.name().unwrap_or_default(), sr, config.sample_format());
+
+        let (mut prod, cons) = HeapRb::<f32>::new(config.sample_rate().0 as usize * CAPTURE_BUFFER_SECS).split();
+        let running = Arc::new(AtomicBool::new(true));
+        let stream_running = running.clone();
+        let stream = build_f32_input_stream(
+            &device,
+            config,
+            // Desktop audio is usually stereo; downmix instead of dropping the right channel.
+            move |frame| {
+                let _ = prod.push(frame.iter().sum::<f32>() / frame.len() as f32);
+            },
+            move |e| {
+                println!("⚠️ Loopback stream error: {}", e);
+                if matches!(e, cpal::StreamError::DeviceNotAvailable) {
+                    stream_running.store(false, Ordering::Relaxed);
+                }
+            },
+        )?;
+
+        let (tx, rx) = mpsc::sync_channel(48000 * CAPTURE_BUFFER_SECS / FRAME_SIZE);
+        let dropped = Arc::new(AtomicU64::new(0));
+        let thread_dropped = dropped.clone();
+        let thread_running = running.clone();
+        let thread = std::thread::spawn(move || {
+            let mut capture = CaptureResampler::new(cons, sr, ResamplerQuality::High, 1);
+            while thread_running.load(Ordering::Relaxed) {
+                capture.poll();
+                while let Some(frame) = capture.next_frame() {
+                    match tx.try_send(frame) {
+                        Ok(()) => {}
+                        Err(mpsc::TrySendError::Full(_)) => {
+                            thread_dropped.fetch_add(FRAME_SIZE as u64, Ordering::Relaxed);
+                        }
+                        Err(mpsc::TrySendError::Disconnected(_)) => return,
+                    }
+                }
+                std::thread::sleep(Duration::from_millis(1));
+            }
+        });
+
+        let loopback = Self { stream, running, thread: Some(thread), dropped };
+        loopback.stream.play()?;
+        Ok((loopback, rx))
+    }
+
+    /// The dropped-sample counter, for an `OverflowMonitor` at 48kHz mono.
+    pub fn dropped(&self) -> Arc<AtomicU64> {
+        self.dropped.clone()
+    }
+}
+
+impl Drop for SystemAudioLoopback {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(handle) = self.thread.take() {
+            let _ = handle.join();
+        }
+    }
+}
@@ -0,0 +1,172 @@
+//! EBU R128 / ITU-R BS.1770 loudness metering and a slow output normalizer.
+
+use std::collections::VecDeque;
+
+use crate::biquad::Biquad;
+
+/// 100ms at 48kHz; four of these make one 400ms gating block (75% overlap).
+const SUB_BLOCK: usize = 4800;
+/// Sliding measurement window in sub-blocks (10s), long enough not to chase syllables.
+const WINDOW_SUB_BLOCKS: usize = 100;
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+const RELATIVE_GATE_LU: f64 = -10.0;
+
+/// Per-10ms-frame smoothing of the applied gain (~2s time constant) so it doesn't pump.
+const GAIN_SMOOTHING: f32 = 0.005;
+const MAX_BOOST_DB: f32 = 12.0;
+const MAX_CUT_DB: f32 = 24.0;
+
+fn mean_square_to_lufs(ms: f64) -> f64 {
+    -0.691 + 10.0 * ms.log10()
+}
+
+/// Gated loudness over the last `WINDOW_SUB_BLOCKS` of 48kHz interleaved audio.
+/// Channel powers are summed with unit weights, as BS.1770 does for L/R.
+pub struct LoudnessMeter {
+    /// K-weighting per channel: high-shelf pre-filter then RLB high-pass (BS.1770 48kHz coefficients).
+    k_weighting: Vec<[Biquad; 2]>,
+    /// Channel of the next pushed sample.
+    channel: usize,
+    acc: f64,
+    /// Complete frames (one sample of every channel) accumulated in `acc`.
+    acc_len: usize,
+    sub_blocks: VecDeque<f64>,
+}
+
+impl LoudnessMeter {
+    pub fn new(channels: usize) -> Self {
+        let k_weighting = [
+            Biquad::new(1.53512485958697, -2.69169618940638, 1.19839281085285, -1.69065929318241, 0.73248077421585),
+            Biquad::new(1.0, -2.0, 1.0, -1.99004745483398, 0.99007225036621),
+        ];
+        Self {
+            k_weighting: vec![k_weighting; channels.max(1)],
+            channel: 0,
+            acc: 0.0,
+            acc_len: 0,
+            sub_blocks: VecDeque::with_capacity(WINDOW_SUB_BLOCKS),
+        }
+    }
+
+    pub fn push(&mut self, samples: &[f32]) {
+        for &s in samples {
+            let filters = &mut self.k_weighting[self.channel];
+            let pre = filters[0].process(s as f64);
+            let y = filters[1].process(pre);
+            self.acc += y * y;
+            self.channel += 1;
+            if self.channel < self.k_weighting.len() {
+                continue;
+            }
+            self.channel = 0;
+            self.acc_len += 1;
+            if self.acc_len == SUB_BLOCK {
+                if self.sub_blocks.len() == WINDOW_SUB_BLOCKS {
+                    self.sub_blocks.pop_front();
+                }
+                self.sub_blocks.push_back(self.acc / SUB_BLOCK as f64);
+                self.acc = 0.0;
+                self.acc_len = 0;
+            }
+        }
+    }
+
+    /// Gated loudness in LUFS, or `None` if nothing in the window passes the gates.
+    pub fn loudness(&self) -> Option<f32> {
+        let subs: Vec<f64> = self.sub_blocks.iter().copied().collect();
+        let blocks: Vec<f64> = subs.windows(4).map(|w| w.iter().sum::<f64>() / 4.0).collect();
+
+        let above_abs: Vec<f64> = blocks.into_iter().filter(|&ms| mean_square_to_lufs(ms) > ABSOLUTE_GATE_LUFS).collect();
+        if above_abs.is_empty() {
+            return None;
+        }
+        let relative_gate = mean_square_to_lufs(above_abs.iter().sum::<f64>() / above_abs.len() as f64) + RELATIVE_GATE_LU;
+        let gated: Vec<f64> = above_abs.into_iter().filter(|&ms| mean_square_to_lufs(ms) > relative_gate).collect();
+        if gated.is_empty() {
+            return None;
+        }
+        Some(mean_square_to_lufs(gated.iter().sum::<f64>() / gated.len() as f64) as f32)
+    }
+}
+
+/// Slowly steers playback towards a target loudness. Silence (gated out) holds
+/// the current gain instead of boosting the noise floor.
+pub struct LoudnessNormalizer {
+    meter: LoudnessMeter,
+    target_lufs: f32,
+    gain_db: f32,
+}
+
+impl LoudnessNormalizer {
+    pub fn new(target_lufs: f32, channels: usize) -> Self {
+        Self { meter: LoudnessMeter::new(channels), target_lufs, gain_db: 0.0 }
+    }
+
+    pub fn target_lufs(&self) -> f32 {
+        self.target_lufs
+    }
+
+    pub fn process(&mut self, frame: &mut [f32]) {
+        self.meter.push(frame);
+        if let Some(lufs) = self.meter.loudness() {
+            let desired = (self.target_lufs - lufs).clamp(-MAX_CUT_DB, MAX_BOOST_DB);
+            self.gain_db += (desired - self.gain_db) * GAIN_SMOOTHING;
+        }
+        let gain = 10f32.powf(self.gain_db / 20.0);
+        for s in frame.iter_mut() {
+            *s = (*s * gain).clamp(-1.0, 1.0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `secs` of a 1kHz sine with `peak_dbfs` amplitude, identical on every channel.
+    fn sine(peak_dbfs: f32, channels: usize, secs: usize) -> Vec<f32> {
+        let amp = 10f32.powf(peak_dbfs / 20.0);
+        (0..48000 * secs)
+            .flat_map(|i| {
+                let s = amp * (2.0 * std::f32::consts::PI * 1000.0 * i as f32 / 48000.0).sin();
+                std::iter::repeat_n(s, channels)
+            })
+            .collect()
+    }
+
+    fn measure(samples: &[f32], channels: usize) -> Option<f32> {
+        let mut meter = LoudnessMeter::new(channels);
+        meter.push(samples);
+        meter.loudness()
+    }
+
+    #[test]
+    fn stereo_sine_at_minus_23_dbfs_reads_minus_23_lufs() {
+        // EBU Tech 3341 case 1.
+        let lufs = measure(&sine(-23.0, 2, 10), 2).unwrap();
+        assert!((lufs + 23.0).abs() < 0.1, "{} LUFS", lufs);
+    }
+
+    #[test]
+    fn full_scale_mono_sine_reads_minus_3_lufs() {
+        let lufs = measure(&sine(0.0, 1, 10), 1).unwrap();
+        assert!((lufs + 3.01).abs() < 0.1, "{} LUFS", lufs);
+    }
+
+    #[test]
+    fn silence_is_gated_out() {
+        assert_eq!(measure(&vec![0.0; 48000 * 5], 1), None);
+    }
+
+    #[test]
+    fn normalizer_steers_towards_the_target() {
+        let mut normalizer = LoudnessNormalizer::new(-16.0, 1);
+        // About -27 LUFS, 11dB below the target.
+        let mut out = sine(-24.0, 1, 40);
+        for frame in out.chunks_mut(480) {
+            normalizer.process(frame);
+        }
+        let lufs = measure(&out[out.len() - 48000 * 5..], 1).unwrap();
+        assert!((lufs + 16.0).abs() < 1.0, "{} LUFS", lufs);
+    }
+}
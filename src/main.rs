@@ -1,13 +1,37 @@
+mod agc;
+mod biquad;
+mod credentials;
+mod denoise;
+mod eq;
+mod latency;
+#[cfg(target_os = "windows")]
+mod loopback;
+mod loudness;
+mod mixer;
+mod resampler;
+mod settings_file;
+
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use std::sync::Arc;
-use std::sync::atomic::{AtomicBool, Ordering};
+use livekit::options::TrackPublishOptions;
+use livekit::track::{LocalAudioTrack, LocalTrack, TrackSource};
+use livekit::webrtc::audio_frame::AudioFrame;
+use livekit::webrtc::audio_source::native::NativeAudioSource;
+use livekit::webrtc::audio_source::{AudioSourceOptions, RtcAudioSource};
+use livekit::{Room, RoomOptions};
+use std::sync::{mpsc, Arc};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
 use parking_lot::Mutex;
-use ringbuf::HeapRb;
-use nnnoiseless::DenoiseState;
-use webrtc_audio_processing::{Processor, InitializationConfig, Config, NoiseSuppression, NoiseSuppressionLevel};
-use rubato::{Resampler, SincFixedIn, SincInterpolationType, SincInterpolationParameters, WindowFunction};
-use std::time::Duration;
+use ringbuf::{HeapConsumer, HeapProducer, HeapRb};
+use webrtc_audio_processing::{Processor, InitializationConfig, Config, EchoCancellationSuppressionLevel, NoiseSuppressionLevel};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant, SystemTime};
 use rdev::{listen, Event, EventType, Key};
+use agc::SoftwareAgc;
+use denoise::{Denoiser, DenoiserFactory, Passthrough, RnNoise};
+use eq::{EqSettings, InputEq};
+use loudness::LoudnessNormalizer;
+use resampler::{AudioResampler, ResamplerQuality};
+use settings_file::SettingsReload;
 
 // --- MODELS ---
 
@@ -19,6 +43,140 @@ struct AudioDeviceInfo {
 
 struct GlobalAudioState {
     is_transmitting: AtomicBool,
+    /// Set while `capture_next_key` waits; the input listener hands it the next key press.
+    key_capture: Mutex<Option<mpsc::Sender<Key>>>,
+    /// Set by `subscribe_transmit_events`.
+    transmit_events: Mutex<Option<TransmitEvents>>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct TransmitEvent {
+    at: Instant,
+    transmitting: bool,
+}
+
+/// A transmit state must hold this long before it is reported, so PTT key
+/// chatter collapses into a single event.
+const TRANSMIT_DEBOUNCE: Duration = Duration::from_millis(50);
+
+/// The transmit event subscriber and its debounce state.
+struct TransmitEvents {
+    tx: mpsc::Sender<TransmitEvent>,
+    reported: bool,
+    /// A change that has not held for `TRANSMIT_DEBOUNCE` yet.
+    pending: Option<TransmitEvent>,
+}
+
+impl TransmitEvents {
+    /// Sends the pending change once it has held long enough. False once the
+    /// receiver is gone.
+    fn flush(&mut self, now: Instant) -> bool {
+        match self.pending {
+            Some(p) if now.duration_since(p.at) >= TRANSMIT_DEBOUNCE => {
+                self.pending = None;
+                self.reported = p.transmitting;
+                self.tx.send(p).is_ok()
+            }
+            _ => true,
+        }
+    }
+}
+
+impl GlobalAudioState {
+    fn new(transmitting: bool) -> Self {
+        Self { is_transmitting: AtomicBool::new(transmitting), key_capture: Mutex::new(None), transmit_events: Mutex::new(None) }
+    }
+
+    /// Waits for the next key press, for "press a key to bind" UIs. It goes through
+    /// the running input listener (rdev can't run a second one), and that press
+    /// doesn't drive the PTT. `None` on timeout, or at once if another capture is
+    /// already waiting.
+    fn capture_next_key(&self, timeout: Duration) -> Option<Key> {
+        let (tx, rx) = mpsc::channel();
+        {
+            let mut slot = self.key_capture.lock();
+            if slot.is_some() {
+                return None;
+            }
+            *slot = Some(tx);
+        }
+        let key = rx.recv_timeout(timeout).ok();
+        // Only this call can have filled the slot, so this never clears another's.
+        self.key_capture.lock().take();
+        key
+    }
+
+    /// Hands `key` to a waiting `capture_next_key`; false if there is none.
+    fn deliver_captured_key(&self, key: Key) -> bool {
+        self.key_capture.lock().take().is_some_and(|tx| tx.send(key).is_ok())
+    }
+
+    /// Sets the transmit state and returns the previous one.
+    fn set_transmitting(&self, transmitting: bool) -> bool {
+        let prev = self.is_transmitting.swap(transmitting, Ordering::Relaxed);
+        if prev != transmitting {
+            self.transmit_changed(transmitting);
+        }
+        prev
+    }
+
+    /// Flips the transmit state and returns the new one.
+    fn toggle_transmitting(&self) -> bool {
+        let now = !self.is_transmitting.fetch_xor(true, Ordering::Relaxed);
+        self.transmit_changed(now);
+        now
+    }
+
+    fn transmit_changed(&self, transmitting: bool) {
+        let mut events = self.transmit_events.lock();
+        let Some(ev) = events.as_mut() else { return };
+        let now = Instant::now();
+        if !ev.flush(now) {
+            *events = None;
+            return;
+        }
+        // A change back to the reported state within the debounce window was chatter.
+        ev.pending = (transmitting != ev.reported).then_some(TransmitEvent { at: now, transmitting });
+    }
+
+    /// Sends a pending transmit event whose debounce window has passed. The input
+    /// listener and the DSP thread call this regularly.
+    fn flush_transmit_events(&self) {
+        let mut events = self.transmit_events.lock();
+        if events.as_mut().is_some_and(|ev| !ev.flush(Instant::now())) {
+            *events = None;
+        }
+    }
+
+    /// Reports debounced transmit start/stop events, replacing any previous
+    /// subscriber. `at` is when the change happened, not when it was confirmed.
+    fn subscribe_transmit_events(&self) -> mpsc::Receiver<TransmitEvent> {
+        let (tx, rx) = mpsc::channel();
+        let reported = self.is_transmitting.load(Ordering::Relaxed);
+        *self.transmit_events.lock() = Some(TransmitEvents { tx, reported, pending: None });
+        rx
+    }
+}
+
+/// How the input device is opened.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum DeviceExclusivity {
+    /// Mixed by the OS with other applications (WASAPI shared mode, ALSA via dmix/PulseAudio).
+    Shared,
+    /// Sole owner of the device for lower latency. The device's native formats
+    /// and rates then apply, with no OS conversion in between. Only on ALSA,
+    /// where the card's `hw:` device is opened instead of the shared one; cpal
+    /// has no WASAPI exclusive mode.
+    Exclusive,
+}
+
+/// How the PTT key drives transmission.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum PttMode {
+    /// Transmit while the key is held.
+    Hold,
+    /// Each press flips transmission on or off.
+    Toggle,
 }
 
 #[derive(Clone)]
@@ -26,8 +184,56 @@ struct AudioSettings {
     input_device_id: String,
     ptt_key: Key,
     ptt_enabled: bool,
+    ptt_mode: PttMode,
+    /// Treat both sides of a modifier PTT key (e.g. left/right Ctrl) as the key.
+    ptt_match_both_modifiers: bool,
     aec_enabled: bool,
+    aec_suppression_level: EchoCancellationSuppressionLevel,
+    aec_delay_agnostic: bool,
+    aec_extended_filter: bool,
+    /// Feed the played-back signal to the processor as the AEC reference.
+    /// Safe to turn off on headphones to save CPU; with speakers it breaks echo cancellation.
+    render_processing_enabled: bool,
     agc_enabled: bool,
+    /// webrtc transient suppressor: tames keyboard clicks but can clip percussive speech.
+    transient_suppressor_enabled: bool,
+    /// Measured mic-to-speaker delay (see `latency::measure_loopback_latency`).
+    /// `None` leaves AEC in pure delay-agnostic mode.
+    aec_stream_delay_ms: Option<u16>,
+    /// Opens the gate this much before the PTT press/VAD trigger, at the cost of the
+    /// same amount of added latency (max 200ms). 0 disables the lookahead.
+    transmit_prebuffer_ms: u16,
+    resampler_quality: ResamplerQuality,
+    dsp_profile: DspProfile,
+    /// 1 (mono voice) or 2 (stereo, e.g. music or game streaming). Stereo keeps
+    /// L/R separate through the whole chain and runs a denoiser per channel.
+    channels: u16,
+    /// Output channels that receive the mono signal (0 = front-left).
+    /// `None` fills FL/FR only, or every channel on mono/stereo devices.
+    output_channel_map: Option<Vec<usize>>,
+    exclusivity: DeviceExclusivity,
+    /// EBU R128 normalization of the local playback path towards this loudness
+    /// (e.g. -16.0). LiveKit sessions ignore it; the remote side sets its own level.
+    output_loudness_target_lufs: Option<f32>,
+    /// Average speaker buffer fill kept by clock-drift compensation. `None` disables it.
+    output_target_fill_ms: Option<u16>,
+    /// Continuous silence/non-transmission after which the session reports itself
+    /// idle (and fires `on_idle_timeout`) so the owner can close it. `None` = never.
+    idle_timeout: Option<Duration>,
+    on_idle_timeout: Option<Arc<dyn Fn() + Send + Sync>>,
+    /// Called from the DSP thread with each frame's RMS level in dBFS, after the
+    /// denoiser and before the PTT gate, so it shows the mic even while muted.
+    /// It runs once per 10ms frame and must return quickly (e.g. store to an atomic).
+    level_callback: Option<Arc<dyn Fn(f32) + Send + Sync>>,
+    /// Custom noise-suppression stage; `None` uses webrtc NS in the main processor
+    /// plus `denoise::default_denoiser`, configured by the two settings below.
+    denoiser: Option<DenoiserFactory>,
+    noise_suppression_level: NoiseSuppressionLevel,
+    /// Extra RNNoise pass after webrtc NS.
+    enable_extra_denoise: bool,
+    /// 3-band EQ applied to the 48kHz mic signal before any other processing,
+    /// so AEC and the denoiser see the corrected spectrum. `None` bypasses it.
+    input_eq: Option<EqSettings>,
 }
 
 impl Default for AudioSettings {
@@ -36,14 +242,134 @@ impl Default for AudioSettings {
             input_device_id: "default".to_string(),
             ptt_key: Key::ControlLeft, // Default PTT key: Left Control
             ptt_enabled: false, // Disabled by default for easier testing
+            ptt_mode: PttMode::Hold,
+            ptt_match_both_modifiers: false,
             aec_enabled: true,
+            aec_suppression_level: EchoCancellationSuppressionLevel::High, // Lower it for headset users
+            aec_delay_agnostic: true,
+            aec_extended_filter: true,
+            render_processing_enabled: true,
             agc_enabled: true,
+            transient_suppressor_enabled: true,
+            aec_stream_delay_ms: None,
+            transmit_prebuffer_ms: 0,
+            resampler_quality: ResamplerQuality::High,
+            dsp_profile: DspProfile::Full,
+            channels: 1,
+            output_channel_map: None,
+            exclusivity: DeviceExclusivity::Shared,
+            output_loudness_target_lufs: None,
+            output_target_fill_ms: Some(20),
+            idle_timeout: None,
+            on_idle_timeout: None,
+            level_callback: None,
+            denoiser: None,
+            noise_suppression_level: NoiseSuppressionLevel::VeryHigh,
+            enable_extra_denoise: true,
+            input_eq: None,
+        }
+    }
+}
+
+/// Frames queued for the LiveKit source before new ones are dropped (200ms).
+const LIVEKIT_QUEUE_FRAMES: usize = 20;
+
+/// Where a session's processed 48kHz mono frames go.
+enum FrameSink {
+    /// The local speaker, resampled to the output device's rate.
+    Loopback(PlaybackResampler),
+    /// A LiveKit track source, fed by a task on the call's runtime. It takes
+    /// 48kHz directly, so no output resampling happens.
+    LiveKit {
+        frames: tokio::sync::mpsc::Sender<AudioFrame<'static>>,
+        num_channels: u32,
+        /// Set while frames are being dropped, so a stall is reported once.
+        dropping: bool,
+    },
+}
+
+impl FrameSink {
+    /// Spawns the task that hands frames to `source` on `runtime`. The DSP
+    /// thread never waits on LiveKit: if the source stops accepting frames the
+    /// queue fills and further frames are dropped. The task ends with the sink.
+    fn livekit(source: Arc<NativeAudioSource>, runtime: &tokio::runtime::Handle) -> Self {
+        let (frames, mut rx) = tokio::sync::mpsc::channel::<AudioFrame<'static>>(LIVEKIT_QUEUE_FRAMES);
+        let num_channels = source.num_channels();
+        runtime.spawn(async move {
+            while let Some(frame) = rx.recv().await {
+                if let Err(e) = source.capture_frame(&frame).await {
+                    println!("⚠️ LiveKit capture_frame failed: {}", e);
+                }
+            }
+        });
+        FrameSink::LiveKit { frames, num_channels, dropping: false }
+    }
+
+    fn push_frame(&mut self, frame: Vec<f32>) {
+        match self {
+            FrameSink::Loopback(playback) => playback.push_frame(frame),
+            FrameSink::LiveKit { frames, num_channels, dropping } => {
+                let data: Vec<i16> = frame.iter().map(|&s| f32_to_i16(s)).collect();
+                let frame = AudioFrame {
+                    data: data.into(),
+                    sample_rate: 48000,
+                    num_channels: *num_channels,
+                    samples_per_channel: FRAME_SIZE as u32,
+                };
+                match frames.try_send(frame) {
+                    Ok(()) => *dropping = false,
+                    Err(tokio::sync::mpsc::error::TrySendError::Full(_)) => {
+                        if !std::mem::replace(dropping, true) {
+                            println!("⚠️ LiveKit is not keeping up, dropping frames");
+                        }
+                    }
+                    // The runtime is shutting down with the call.
+                    Err(tokio::sync::mpsc::error::TrySendError::Closed(_)) => {}
+                }
+            }
         }
     }
 }
 
+/// The loopback output of a session opened with `AudioSession::create`.
+struct SessionOutput {
+    device: cpal::Device,
+    /// `None` only while the stream is being rebuilt.
+    stream: Option<cpal::Stream>,
+    channel_map: Option<Vec<usize>>,
+}
+
+/// Opens the loopback playback stream with its ring buffer.
+/// Returns the buffer's producer, the stream (not yet playing) and the device rate.
+fn open_playback(
+    device: &cpal::Device,
+    channel_map: Option<&[usize]>,
+    channels: usize,
+    health: &Arc<StreamHealth>,
+) -> anyhow::Result<(HeapProducer<f32>, cpal::Stream, f64)> {
+    let (prod, cons) = HeapRb::<f32>::new(48000 * 2 * channels).split();
+    let (stream, sr) = build_playback_stream(device, cons, channel_map, channels, health.on_error(true))?;
+    Ok((prod, stream, sr))
+}
+
 struct AudioSession {
-    _streams: (cpal::Stream, cpal::Stream),
+    in_device: cpal::Device,
+    /// `None` only while the stream is being rebuilt.
+    in_stream: Option<cpal::Stream>,
+    output: Option<SessionOutput>,
+    channels: usize,
+    health: Arc<StreamHealth>,
+    /// Hands rebuilt streams' buffers to the DSP thread.
+    swaps: mpsc::Sender<StreamSwap>,
+    settings_updates: mpsc::Sender<AudioSettings>,
+    idle_timed_out: Arc<AtomicBool>,
+    /// Input samples lost to capture ring buffer overflow since the session opened.
+    dropped_input: Arc<AtomicU64>,
+    sample_rates: Arc<SampleRates>,
+    /// Cleared to make the DSP thread exit.
+    running: Arc<AtomicBool>,
+    dsp_thread: Option<std::thread::JoinHandle<()>>,
+    rate_watcher: Option<std::thread::JoinHandle<()>>,
 }
 
 // --- DEVICE DISCOVERY ---
@@ -121,263 +447,2264 @@ fn get_professional_device_list(host: &cpal::Host) -> Vec<AudioDeviceInfo> {
     list
 }
 
-// --- SESSION LOGIC ---
+// --- DEVICE RESOLUTION ---
 
-impl AudioSession {
-        fn create(in_id: &str, state: Arc<GlobalAudioState>, settings: AudioSettings) -> anyhow::Result<Self> {
-            let host = cpal::default_host();
-            let in_device = if in_id == "default" {
-                host.default_input_device().ok_or_else(|| anyhow::anyhow!("No mic found"))?
-            } else {
-                let mut devices = host.input_devices()?;
-                let mut found = devices.find(|d| d.name().unwrap_or_default() == in_id);
-                
-                if found.is_none() {
-                    // FALLBACK: Try to find by partial match (e.g. if sysdefault is gone, try plughw)
-                    if let Some(card_name) = in_id.split("CARD=").nth(1).and_then(|s| s.split(',').next()) {
-                        println!("⚠️ Exact match not found, trying fallback for card: '{}'", card_name);
-                        // Re-acquire iterator as the previous one was consumed
-                        let mut devices_retry = host.input_devices()?;
-                        found = devices_retry.find(|d| d.name().unwrap_or_default().contains(card_name));
-                        if let Some(ref d) = found {
-                            println!("🔄 Fallback found: {}", d.name().unwrap_or_default());
-                        }
-                    }
-                }
-    
-                match found {
-                    Some(d) => d,
-                    None => {
-                        println!("⚠️  Could not find '{}'. Available devices:", in_id);
-                        for d in host.input_devices()? {
-                            println!("   - '{}'", d.name().unwrap_or_default());
-                        }
-                        return Err(anyhow::anyhow!("Device not found: {}", in_id));
-                    },
-                }
-            };
-            
-            let out_device = host.default_output_device().ok_or_else(|| anyhow::anyhow!("No speaker found"))?;
-            let in_config = in_device.default_input_config()?;
-            let out_config = out_device.default_output_config()?;
-    
-            let in_sr = in_config.sample_rate().0 as f64;
-            let out_sr = out_config.sample_rate().0 as f64;
-            let in_format = in_config.sample_format();
-    
-            println!("🎙️  Opening: {} ({}Hz, {:?})", in_id, in_sr, in_format);
-    
-            let (mut prod_in, mut cons_in) = HeapRb::<f32>::new(48000 * 2).split();
-            let (mut prod_out, mut cons_out) = HeapRb::<f32>::new(48000 * 2).split();
-    
-            let in_ch = in_config.channels() as usize;
-            let _in_stream = match in_format {
-                cpal::SampleFormat::F32 => in_device.build_input_stream(&in_config.into(), move |data: &[f32], _| {
-                    for chunk in data.chunks(in_ch) { let _ = prod_in.push(chunk[0]); }
-                }, |_| {}, None)?,
-                cpal::SampleFormat::I16 => in_device.build_input_stream(&in_config.into(), move |data: &[i16], _| {
-                    for chunk in data.chunks(in_ch) { let _ = prod_in.push(chunk[0] as f32 / i16::MAX as f32); }
-                }, |_| {}, None)?,
-                _ => return Err(anyhow::anyhow!("Unsupported format")),
-            };
-    
-            let out_ch = out_config.channels() as usize;
-            let _out_stream = out_device.build_output_stream(&out_config.into(), move |data: &mut [f32], _| {
-                for chunk in data.chunks_mut(out_ch) {
-                    let s = cons_out.pop().unwrap_or(0.0);
-                    for ch in chunk.iter_mut() { *ch = s; }
-                }
-            }, |_| {}, None)?;
-    
-            std::thread::spawn(move || {
-                let mut denoise = DenoiseState::new();
-                let mut proc = Processor::new(&InitializationConfig { 
-                    num_capture_channels: 1, 
-                    num_render_channels: 1, 
-                    ..Default::default() 
-                }).unwrap();
-                
-                proc.set_config(Config { 
-                    noise_suppression: Some(NoiseSuppression { suppression_level: NoiseSuppressionLevel::VeryHigh }), 
-                    echo_cancellation: if settings.aec_enabled { Some(webrtc_audio_processing::EchoCancellation {
-                        suppression_level: webrtc_audio_processing::EchoCancellationSuppressionLevel::High,
-                        stream_delay_ms: None, 
-                        enable_delay_agnostic: true,
-                        enable_extended_filter: true,
-                    }) } else { None },
-                    gain_control: if settings.agc_enabled { Some(webrtc_audio_processing::GainControl {
-                        mode: webrtc_audio_processing::GainControlMode::AdaptiveDigital,
-                        target_level_dbfs: 3,
-                        compression_gain_db: 15,
-                        enable_limiter: true,
-                    }) } else { None },
-                    enable_high_pass_filter: true, 
-                    enable_transient_suppressor: true, 
-                    ..Default::default() 
-                });
-
-            let params_in = SincInterpolationParameters { sinc_len: 256, f_cutoff: 0.95, interpolation: SincInterpolationType::Linear, window: WindowFunction::BlackmanHarris2, oversampling_factor: 256 };
-            let params_out = SincInterpolationParameters { sinc_len: 256, f_cutoff: 0.95, interpolation: SincInterpolationType::Linear, window: WindowFunction::BlackmanHarris2, oversampling_factor: 256 };
-            
-            let mut res_in = SincFixedIn::<f32>::new(48000.0 / in_sr, 2.0, params_in, 480, 1).unwrap();
-            let mut res_out = SincFixedIn::<f32>::new(out_sr / 48000.0, 2.0, params_out, 480, 1).unwrap();
-
-            let mut dsp_buf = Vec::new();
-            loop {
-                let needed = res_in.input_frames_next();
-                if cons_in.len() >= needed {
-                    let mut chunk = vec![0.0f32; needed];
-                    for s in chunk.iter_mut() { *s = cons_in.pop().unwrap(); }
-                    if let Ok(res) = res_in.process(&[chunk], None) {
-                        dsp_buf.extend_from_slice(&res[0]);
-                        while dsp_buf.len() >= 480 {
-                            let mut frame = dsp_buf.drain(0..480).collect::<Vec<_>>();
-                            
-                            // 1. Process Capture (Microphone -> Clean)
-                            let _ = proc.process_capture_frame(&mut frame);
-                            
-                            // 2. Extra Denoise
-                            let mut clean = [0.0f32; 480];
-                            denoise.process_frame(&mut clean, &frame);
-                            
-                            // 3. PTT Gate
-                            let is_tx = state.is_transmitting.load(Ordering::Relaxed);
-                            let output_frame = if is_tx { clean.to_vec() } else { vec![0.0; 480] };
-
-                            // 4. Feed Render (Speaker -> AEC Reference)
-                            // In a real VoIP app, this would be the incoming network audio.
-                            // Here in loopback, we feed our own output to simulate "speaker signal".
-                            // Important: We must clone because process_render_frame consumes or mutates.
-                            let mut render_copy = output_frame.clone(); 
-                            let _ = proc.process_render_frame(&mut render_copy);
-
-                            // 5. Output to Speaker
-                            if let Ok(res_o) = res_out.process(&[output_frame], None) {
-                                for &s in &res_o[0] { 
-                                    let _ = prod_out.push(s); 
-                                }
-                            }
-                        }
-                    }
-                }
-                std::thread::sleep(Duration::from_millis(1));
-            }
-        });
+#[derive(Debug)]
+enum AudioError {
+    NoDefaultDevice(&'static str),
+    DeviceNotFound(String),
+    Backend(String),
+    Unsupported(&'static str),
+}
 
-        _in_stream.play()?;
-        _out_stream.play()?;
-        Ok(Self { _streams: (_in_stream, _out_stream) })
+impl std::fmt::Display for AudioError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AudioError::NoDefaultDevice(kind) => write!(f, "No default {} device found", kind),
+            AudioError::DeviceNotFound(id) => write!(f, "Device not found: {}", id),
+            AudioError::Backend(msg) => write!(f, "Audio backend error: {}", msg),
+            AudioError::Unsupported(what) => write!(f, "Not supported: {}", what),
+        }
     }
 }
 
-fn main() -> anyhow::Result<()> {
-    #[cfg(target_os = "linux")]
-    unsafe { libc::close(2); }
-    env_logger::init();
-    let host = cpal::default_host();
-    let settings = Arc::new(Mutex::new(AudioSettings::default()));
-    
-    // --- SHARED STATE & INPUT HANDLING ---
-    let global_state = Arc::new(GlobalAudioState { 
-        is_transmitting: AtomicBool::new(true) // Start transmitting by default
-    });
+impl std::error::Error for AudioError {}
 
-    let input_state = global_state.clone();
-    let input_settings = settings.clone();
-    
-    std::thread::spawn(move || {
-        println!("⌨️  Global Input Listener started (rdev)");
-        
-        // This callback will be called for every input event
-        let callback = move |event: Event| {
-            let (target_key, enabled) = {
-                let s = input_settings.lock();
-                (s.ptt_key, s.ptt_enabled)
-            };
+/// The device name to open for `id`. Exclusive access maps an ALSA card id
+/// (`plughw:CARD=PCH,DEV=0`, `sysdefault:CARD=PCH`) to the card's `hw:` device,
+/// which bypasses dsnoop and the sound server. Other backends refuse it.
+fn input_device_id(id: &str, exclusivity: DeviceExclusivity) -> Result<String, AudioError> {
+    match exclusivity {
+        DeviceExclusivity::Shared => Ok(id.to_string()),
+        DeviceExclusivity::Exclusive if cfg!(target_os = "linux") => {
+            let rest = id
+                .split_once("CARD=")
+                .ok_or(AudioError::Unsupported("exclusive access without a card id such as plughw:CARD=PCH,DEV=0"))?
+                .1;
+            let card = rest.split(',').next().unwrap_or(rest);
+            let dev = rest.split_once("DEV=").map_or("0", |(_, d)| d.split(',').next().unwrap_or(d));
+            Ok(format!("hw:CARD={},DEV={}", card, dev))
+        }
+        DeviceExclusivity::Exclusive => Err(AudioError::Unsupported("exclusive device access on this audio backend")),
+    }
+}
 
-            if !enabled {
-                input_state.is_transmitting.store(true, Ordering::Relaxed);
-                return;
+/// Resolves the capture device for `id` with the requested exclusivity.
+fn open_input_device(host: &cpal::Host, id: &str, exclusivity: DeviceExclusivity) -> Result<cpal::Device, AudioError> {
+    let device_id = input_device_id(id, exclusivity)?;
+    let device = resolve_input_device(host, &device_id)?;
+    // The card fallback in `match_device_name` would pick a shared device instead.
+    if exclusivity == DeviceExclusivity::Exclusive && device.name().ok().as_deref() != Some(device_id.as_str()) {
+        return Err(AudioError::DeviceNotFound(device_id));
+    }
+    Ok(device)
+}
+
+/// Explains stream errors on an exclusively opened device, where the usual cause
+/// is another application holding it.
+fn exclusive_hint(e: anyhow::Error, exclusivity: DeviceExclusivity) -> anyhow::Error {
+    match exclusivity {
+        DeviceExclusivity::Shared => e,
+        DeviceExclusivity::Exclusive => e.context("Cannot open the device exclusively; is another application using it?"),
+    }
+}
+
+fn check_channels(channels: u16) -> anyhow::Result<()> {
+    if !(1..=2).contains(&channels) {
+        return Err(anyhow::anyhow!("channels must be 1 or 2, got {}", channels));
+    }
+    Ok(())
+}
+
+/// Rejects settings the DSP chain can't run with.
+fn check_settings(settings: &AudioSettings) -> anyhow::Result<()> {
+    check_channels(settings.channels)?;
+    if let Some(eq) = &settings.input_eq {
+        eq.validate()?;
+    }
+    Ok(())
+}
+
+/// Picks the device matching `id` from a list of device names.
+/// Exact match wins; otherwise falls back to the first name containing the
+/// `CARD=` part of `id` (e.g. `sysdefault:CARD=PCH` -> `plughw:CARD=PCH,DEV=0`).
+fn match_device_name(names: &[String], id: &str) -> Option<usize> {
+    if let Some(i) = names.iter().position(|n| n == id) {
+        return Some(i);
+    }
+    let card_name = id.split("CARD=").nth(1).and_then(|s| s.split(',').next())?;
+    println!("⚠️ Exact match not found, trying fallback for card: '{}'", card_name);
+    let i = names.iter().position(|n| n.contains(card_name))?;
+    println!("🔄 Fallback found: {}", names[i]);
+    Some(i)
+}
+
+/// Resolves `id` against `(name, device)` pairs. "default" takes `default()`
+/// without listing anything; other ids go through `match_device_name`.
+/// Generic over the device so the lookup can be tested without a sound card.
+fn resolve_device<D>(
+    id: &str,
+    kind: &'static str,
+    default: impl FnOnce() -> Option<D>,
+    devices: impl FnOnce() -> Result<Vec<(String, D)>, AudioError>,
+) -> Result<D, AudioError> {
+    if id == "default" {
+        return default().ok_or(AudioError::NoDefaultDevice(kind));
+    }
+    let (names, mut devices): (Vec<String>, Vec<D>) = devices()?.into_iter().unzip();
+    match match_device_name(&names, id) {
+        Some(i) => Ok(devices.swap_remove(i)),
+        None => {
+            println!("⚠️  Could not find '{}'. Available devices:", id);
+            for name in &names {
+                println!("   - '{}'", name);
             }
+            Err(AudioError::DeviceNotFound(id.to_string()))
+        }
+    }
+}
 
-            match event.event_type {
-                EventType::KeyPress(key) => {
-                    if key == target_key {
-                        let prev = input_state.is_transmitting.swap(true, Ordering::Relaxed);
-                        if !prev {
-                            print!("🎤 ");
-                            use std::io::Write;
-                            let _ = std::io::stdout().flush();
-                        }
-                    }
-                },
-                EventType::KeyRelease(key) => {
-                    if key == target_key {
-                        input_state.is_transmitting.store(false, Ordering::Relaxed);
-                        print!("🔇 ");
-                        use std::io::Write;
-                        let _ = std::io::stdout().flush();
-                    }
-                },
-                _ => {}
+fn named_devices(devices: impl Iterator<Item = cpal::Device>) -> Vec<(String, cpal::Device)> {
+    devices.map(|d| (d.name().unwrap_or_default(), d)).collect()
+}
+
+fn resolve_input_device(host: &cpal::Host, id: &str) -> Result<cpal::Device, AudioError> {
+    resolve_device(id, "input", || host.default_input_device(), || {
+        Ok(named_devices(host.input_devices().map_err(|e| AudioError::Backend(e.to_string()))?))
+    })
+}
+
+fn resolve_output_device(host: &cpal::Host, id: &str) -> Result<cpal::Device, AudioError> {
+    resolve_device(id, "output", || host.default_output_device(), || {
+        Ok(named_devices(host.output_devices().map_err(|e| AudioError::Backend(e.to_string()))?))
+    })
+}
+
+// --- DEFAULT DEVICE TRACKING ---
+
+/// How often the OS default devices are re-queried.
+const DEFAULT_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// cpal binds to a concrete device when a stream is built, so a session opened
+/// on "default" would keep using the old device after the OS default changes.
+/// This watches the default input/output names so the session can be rebuilt.
+/// (On ALSA the default is a plugin named "default" that follows the sound
+/// server by itself, so this only ever fires on WASAPI/CoreAudio.)
+struct DefaultDeviceWatcher {
+    input: Option<String>,
+    output: Option<String>,
+    last_check: Instant,
+}
+
+impl DefaultDeviceWatcher {
+    fn new(host: &cpal::Host) -> Self {
+        let (input, output) = Self::query(host);
+        Self { input, output, last_check: Instant::now() }
+    }
+
+    fn query(host: &cpal::Host) -> (Option<String>, Option<String>) {
+        (
+            host.default_input_device().and_then(|d| d.name().ok()),
+            host.default_output_device().and_then(|d| d.name().ok()),
+        )
+    }
+
+    /// Records the latest default names; returns `(input_changed, output_changed)`.
+    fn update(&mut self, input: Option<String>, output: Option<String>) -> (bool, bool) {
+        let changed = (input != self.input, output != self.output);
+        self.input = input;
+        self.output = output;
+        changed
+    }
+
+    /// `None` between checks, otherwise which defaults changed since the last check.
+    fn poll(&mut self, host: &cpal::Host) -> Option<(bool, bool)> {
+        if self.last_check.elapsed() < DEFAULT_CHECK_INTERVAL {
+            return None;
+        }
+        self.last_check = Instant::now();
+        let (input, output) = Self::query(host);
+        Some(self.update(input, output))
+    }
+}
+
+/// Whether a default-device change affects a session on input `session_id`. Only
+/// "default" follows the input default; a local output (`has_output`) is always
+/// opened on "default", while a LiveKit session has none to follow.
+fn default_change_needs_reopen(session_id: &str, has_output: bool, (input_changed, output_changed): (bool, bool)) -> bool {
+    (input_changed && session_id == "default") || (output_changed && has_output)
+}
+
+// --- SESSION LOGIC ---
+
+/// Samples per 10ms frame at the fixed 48kHz processing rate (webrtc + RNNoise frame size).
+const FRAME_SIZE: usize = 480;
+
+/// Seconds of device-rate input the capture ring buffer holds.
+const CAPTURE_BUFFER_SECS: usize = 2;
+
+/// Opens `device` as an input stream with `config` and calls `on_frame` with each
+/// device frame (all channels) converted to f32. Shared by every capture path.
+/// Returns the stream, not yet playing.
+fn build_f32_input_stream(
+    device: &cpal::Device,
+    config: cpal::SupportedStreamConfig,
+    mut on_frame: impl FnMut(&[f32]) + Send + 'static,
+    on_error: impl FnMut(cpal::StreamError) + Send + 'static,
+) -> anyhow::Result<cpal::Stream> {
+    let format = config.sample_format();
+    let ch = config.channels() as usize;
+    let config: cpal::StreamConfig = config.into();
+    let mut frame = vec![0.0f32; ch];
+    let stream = match format {
+        cpal::SampleFormat::F32 => device.build_input_stream(&config, move |data: &[f32], _| {
+            for chunk in data.chunks(ch) { on_frame(chunk); }
+        }, on_error, None)?,
+        cpal::SampleFormat::I16 => device.build_input_stream(&config, move |data: &[i16], _| {
+            for chunk in data.chunks(ch) {
+                for (f, &s) in frame.iter_mut().zip(chunk) { *f = s as f32 / i16::MAX as f32; }
+                on_frame(&frame[..chunk.len()]);
             }
-        };
+        }, on_error, None)?,
+        // 24-bit interfaces (S24 in a 32-bit container) arrive here too
+        cpal::SampleFormat::I32 => device.build_input_stream(&config, move |data: &[i32], _| {
+            for chunk in data.chunks(ch) {
+                for (f, &s) in frame.iter_mut().zip(chunk) { *f = i32_to_f32(s); }
+                on_frame(&frame[..chunk.len()]);
+            }
+        }, on_error, None)?,
+        _ => return Err(anyhow::anyhow!("Unsupported input format: {:?}", format)),
+    };
+    Ok(stream)
+}
 
-        if let Err(error) = listen(callback) {
-            println!("❌ Input Error: {:?}", error);
+/// Opens `device` for capture and pushes `channels` interleaved samples per device
+/// frame into a ring buffer of `CAPTURE_BUFFER_SECS`: the device's first channels,
+/// with a mono device duplicated. Samples dropped because the buffer is full are
+/// added to `dropped`; stream errors (e.g. the device going away) go to `on_error`.
+/// Returns the stream (not yet playing), the device sample rate and the buffer's consumer.
+fn build_capture_stream(
+    device: &cpal::Device,
+    channels: usize,
+    dropped: Arc<AtomicU64>,
+    on_error: impl FnMut(cpal::StreamError) + Send + 'static,
+) -> anyhow::Result<(cpal::Stream, f64, HeapConsumer<f32>)> {
+    let config = device.default_input_config()?;
+    let sr = config.sample_rate().0 as f64;
+
+    println!("🎙️  Opening: {} ({}Hz, {:?})", device.name().unwrap_or_default(), sr, config.sample_format());
+
+    let (mut prod, cons) = HeapRb::<f32>::new(config.sample_rate().0 as usize * CAPTURE_BUFFER_SECS * channels).split();
+    // Whole frames only, so a full buffer can never shift L/R out of step.
+    let push_frame = move |chunk: &[f32]| {
+        if prod.free_len() >= channels {
+            for c in 0..channels { let _ = prod.push(chunk[c.min(chunk.len() - 1)]); }
+        } else {
+            dropped.fetch_add(channels as u64, Ordering::Relaxed);
         }
-    });
+    };
+    let stream = build_f32_input_stream(device, config, push_frame, on_error)?;
+    Ok((stream, sr, cons))
+}
 
-    println!("\n=== NEANDERTAL VOIP CORE AUDIO DEVICE LIST ===");
-    let inputs = get_professional_device_list(&host);
-    for (i, dev) in inputs.iter().enumerate() { println!("{}. {}", i, dev.display_name); }
-    println!("==============================\n");
+/// Dropped input per second above which a warning is logged.
+const OVERFLOW_WARN_MS: u64 = 10;
 
-    // Prioritize "System Default" devices for better compatibility
-    let _alt_mic = inputs.iter()
-        .find(|d| d.id != "default" && d.display_name.contains("System Default") && (d.display_name.contains("Dahili") || d.display_name.contains("USB")))
-        .or_else(|| inputs.iter().find(|d| d.id != "default" && (d.display_name.contains("Dahili") || d.display_name.contains("USB"))))
-        .cloned()
-        .unwrap_or_else(|| inputs.get(inputs.len() - 1).cloned().unwrap());
+/// Reports capture ring buffer overflows (the DSP thread falling behind the
+/// device), at most once a second.
+struct OverflowMonitor {
+    label: String,
+    dropped: Arc<AtomicU64>,
+    /// Samples the ring buffer holds per device frame.
+    channels: usize,
+    /// `OVERFLOW_WARN_MS` of interleaved samples at the device rate.
+    warn_samples: u64,
+    reported: u64,
+    last_check: Instant,
+}
 
-    // START WITH DEFAULT SETTINGS
-    // settings.lock().input_device_id is "default" by the Default impl.
-    
-    // Select initial device based on settings (which is "default")
-    // If we wanted to persist settings, we would load them here.
+impl OverflowMonitor {
+    /// `sample_rate` and `channels` describe the ring buffer `dropped` counts for.
+    fn new(label: impl Into<String>, dropped: Arc<AtomicU64>, sample_rate: f64, channels: usize) -> Self {
+        let mut monitor = Self { label: label.into(), dropped, channels, warn_samples: 0, reported: 0, last_check: Instant::now() };
+        monitor.set_sample_rate(sample_rate);
+        monitor
+    }
 
-    let mut _session: Option<AudioSession> = None;
-    let mut last_id = String::new();
-    
-    // Initial start
-    // We let the loop handle the first start to reuse logic
+    /// Follows the input to a rebuilt stream at a new rate.
+    fn set_sample_rate(&mut self, sample_rate: f64) {
+        self.warn_samples = sample_rate as u64 * self.channels as u64 / 1000 * OVERFLOW_WARN_MS;
+    }
 
-    loop {
-        let (current_id, current_settings) = {
-            let s = settings.lock();
-            (s.input_device_id.clone(), s.clone())
-        };
+    fn poll(&mut self) {
+        if self.last_check.elapsed() < Duration::from_secs(1) {
+            return;
+        }
+        self.last_check = Instant::now();
+        let total = self.dropped.load(Ordering::Relaxed);
+        let new = total - self.reported;
+        self.reported = total;
+        if new > self.warn_samples {
+            println!("⚠️ {}: input buffer overflowed, dropped {} samples in the last second ({} total)", self.label, new, total);
+        }
+    }
+}
 
-        if current_id != last_id {
-            if _session.is_some() {
-                println!("🛑 Closing old session...");
-                _session = None; // Explicit drop
+/// cpal hands 24-bit audio over as `I32` with the 24 significant bits in the top
+/// of the word (WASAPI/CoreAudio "24 in 32"), so full-scale is 2^31 here, not 2^23.
+fn i32_to_f32(s: i32) -> f32 {
+    s as f32 / 2_147_483_648.0
+}
+
+/// Which output channels carry the mono signal. On surround devices voice
+/// belongs on front-left/front-right only, never on the rear or LFE channels.
+fn output_channel_mask(channels: usize, channel_map: Option<&[usize]>) -> Vec<bool> {
+    match channel_map {
+        Some(map) => (0..channels).map(|c| map.contains(&c)).collect(),
+        None => (0..channels).map(|c| channels <= 2 || c < 2).collect(),
+    }
+}
+
+/// What one output channel plays, given the session's (mono or stereo) signal.
+#[derive(Clone, Copy)]
+enum OutputRoute {
+    Silent,
+    Source(usize),
+    /// Stereo folded down for a mono device.
+    Downmix,
+}
+
+impl OutputRoute {
+    fn sample(self, frame: &[f32; 2]) -> f32 {
+        match self {
+            OutputRoute::Silent => 0.0,
+            OutputRoute::Source(c) => frame[c],
+            OutputRoute::Downmix => (frame[0] + frame[1]) * 0.5,
+        }
+    }
+}
+
+/// Per output channel routing. Mono follows `output_channel_mask`; stereo goes
+/// L/R to front-left/front-right, or is downmixed on a mono device.
+fn output_routing(channels: usize, channel_map: Option<&[usize]>, source_channels: usize) -> Vec<OutputRoute> {
+    if source_channels == 1 {
+        return output_channel_mask(channels, channel_map)
+            .into_iter()
+            .map(|on| if on { OutputRoute::Source(0) } else { OutputRoute::Silent })
+            .collect();
+    }
+    if channels == 1 {
+        return vec![OutputRoute::Downmix];
+    }
+    (0..channels).map(|c| if c < 2 { OutputRoute::Source(c) } else { OutputRoute::Silent }).collect()
+}
+
+/// Rejects channel layouts the playback callback can't serve. The callback assumes
+/// cpal's interleaved buffers (`[c0, c1, .., c0, c1, ..]`), one chunk per frame.
+fn validate_output_channels(channels: usize, channel_map: Option<&[usize]>, source_channels: usize) -> anyhow::Result<()> {
+    if channels == 0 {
+        return Err(anyhow::anyhow!("Output device reports 0 channels"));
+    }
+    if source_channels > 1 && channel_map.is_some() {
+        return Err(anyhow::anyhow!("output_channel_map only applies to mono sessions"));
+    }
+    if let Some(map) = channel_map {
+        if map.is_empty() {
+            return Err(anyhow::anyhow!("output_channel_map selects no channels"));
+        }
+        if let Some(&bad) = map.iter().find(|&&c| c >= channels) {
+            return Err(anyhow::anyhow!("output_channel_map uses channel {} but the device only has {} channels", bad, channels));
+        }
+    }
+    Ok(())
+}
+
+/// Opens `device` for playback (F32 or I16), reading `source_channels` (1 or 2)
+/// interleaved samples per frame from `cons` and routing them with `output_routing`.
+/// A mono signal goes to the channels selected by `channel_map` (see `output_channel_mask`).
+/// Stream errors go to `on_error`. Returns the stream (not yet playing) and the device sample rate.
+fn build_playback_stream(
+    device: &cpal::Device,
+    mut cons: HeapConsumer<f32>,
+    channel_map: Option<&[usize]>,
+    source_channels: usize,
+    on_error: impl FnMut(cpal::StreamError) + Send + 'static,
+) -> anyhow::Result<(cpal::Stream, f64)> {
+    let config = device.default_output_config()?;
+    let sr = config.sample_rate().0 as f64;
+    let format = config.sample_format();
+    let ch = config.channels() as usize;
+    validate_output_channels(ch, channel_map, source_channels)?;
+    let routing = output_routing(ch, channel_map, source_channels);
+
+    // Pops whole frames only (silence on underrun), so L/R can't drift out of step.
+    let mut pop_frame = move || {
+        let mut frame = [0.0f32; 2];
+        if cons.len() >= source_channels {
+            for s in frame[..source_channels].iter_mut() { *s = cons.pop().unwrap_or(0.0); }
+        }
+        frame
+    };
+    let stream = match format {
+        cpal::SampleFormat::F32 => device.build_output_stream(&config.into(), move |data: &mut [f32], _| {
+            for chunk in data.chunks_mut(ch) {
+                let frame = pop_frame();
+                for (c, r) in chunk.iter_mut().zip(&routing) { *c = r.sample(&frame); }
             }
-            
-            // Wait for device to be released by OS/ALSA
-            std::thread::sleep(Duration::from_millis(1000));
-            
-            match AudioSession::create(&current_id, global_state.clone(), current_settings) {
-                Ok(s) => { _session = Some(s); last_id = current_id; println!("✅ Active."); } 
-                Err(e) => { 
-                    println!("❌ Failed to open '{}': {:?}", current_id, e);
-                    // Don't update last_id so it retries or allows UI to show error state
-                    // Actually, if we fail, we might want to stay in "failed" state or fallback.
-                    // For now, let's update last_id to prevent infinite retry loop on same ID
-                    last_id = current_id; 
-                }
+        }, on_error, None)?,
+        cpal::SampleFormat::I16 => device.build_output_stream(&config.into(), move |data: &mut [i16], _| {
+            for chunk in data.chunks_mut(ch) {
+                let frame = pop_frame();
+                for (c, r) in chunk.iter_mut().zip(&routing) { *c = f32_to_i16(r.sample(&frame)); }
+            }
+        }, on_error, None)?,
+        _ => return Err(anyhow::anyhow!("Unsupported output format: {:?}", format)),
+    };
+    Ok((stream, sr))
+}
+
+/// Converts a DSP sample to i16, clamping anything outside [-1.0, 1.0].
+fn f32_to_i16(s: f32) -> i16 {
+    (s.clamp(-1.0, 1.0) * i16::MAX as f32) as i16
+}
+
+/// Pulls device-rate samples from a capture ring buffer and yields 48kHz frames
+/// of `FRAME_SIZE` samples per channel, interleaved.
+struct CaptureResampler {
+    cons: HeapConsumer<f32>,
+    res: AudioResampler,
+    buf: Vec<f32>,
+    /// Reused input chunk for the resampler; `filled` samples are valid.
+    chunk: Vec<f32>,
+    filled: usize,
+    in_sr: f64,
+    quality: ResamplerQuality,
+    channels: usize,
+}
+
+impl CaptureResampler {
+    fn new(cons: HeapConsumer<f32>, in_sr: f64, quality: ResamplerQuality, channels: usize) -> Self {
+        Self {
+            cons,
+            res: AudioResampler::new(in_sr, 48000.0, quality, channels),
+            buf: Vec::new(),
+            chunk: Vec::new(),
+            filled: 0,
+            in_sr,
+            quality,
+            channels,
+        }
+    }
+
+    /// Switches to a rebuilt capture stream's buffer and rate. The partial chunk
+    /// from the old stream is discarded; already resampled 48kHz audio is kept.
+    fn replace_input(&mut self, cons: HeapConsumer<f32>, in_sr: f64) {
+        self.cons = cons;
+        self.res = AudioResampler::new(in_sr, 48000.0, self.quality, self.channels);
+        self.in_sr = in_sr;
+        self.filled = 0;
+    }
+
+    /// Resamples whatever input is available into the internal 48kHz buffer.
+    /// A short read just leaves the chunk partially filled until the next poll.
+    fn poll(&mut self) {
+        let needed = self.res.input_frames_next() * self.channels;
+        if self.chunk.len() != needed {
+            self.chunk.resize(needed, 0.0);
+            self.filled = self.filled.min(needed);
+        }
+        self.filled += self.cons.pop_slice(&mut self.chunk[self.filled..]);
+        if self.filled == needed {
+            self.filled = 0;
+            if let Some(res) = self.res.process(&self.chunk) {
+                self.buf.extend_from_slice(&res);
             }
         }
-        std::thread::sleep(Duration::from_millis(100));
     }
-}
\ No newline at end of file
+
+    fn buffered(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Drops the oldest buffered samples so at most `max` remain, in whole frames.
+    fn trim_backlog(&mut self, max: usize) {
+        if self.buf.len() > max {
+            let excess = (self.buf.len() - max).next_multiple_of(self.channels);
+            self.buf.drain(0..excess.min(self.buf.len()));
+        }
+    }
+
+    fn next_frame(&mut self) -> Option<Vec<f32>> {
+        let len = FRAME_SIZE * self.channels;
+        if self.buf.len() >= len {
+            Some(self.buf.drain(0..len).collect())
+        } else {
+            None
+        }
+    }
+}
+
+/// Largest relative ratio correction used for drift compensation (0.5%), inaudible as pitch.
+const MAX_DRIFT_CORRECTION: f64 = 0.005;
+/// Smoothing of the measured output fill per pushed frame (~1s time constant).
+const FILL_SMOOTHING: f64 = 0.01;
+
+/// Resamples processed 48kHz frames to the output device rate and queues them for playback.
+struct PlaybackResampler {
+    prod: HeapProducer<f32>,
+    res: AudioResampler,
+    out_sr: f64,
+    quality: ResamplerQuality,
+    channels: usize,
+    /// Desired steady-state output buffer fill, in ms. `None` disables drift compensation.
+    target_fill_ms: Option<u16>,
+    avg_fill: f64,
+}
+
+impl PlaybackResampler {
+    fn new(prod: HeapProducer<f32>, out_sr: f64, quality: ResamplerQuality, channels: usize) -> Self {
+        Self {
+            prod,
+            res: AudioResampler::new(48000.0, out_sr, quality, channels),
+            out_sr,
+            quality,
+            channels,
+            target_fill_ms: None,
+            avg_fill: 0.0,
+        }
+    }
+
+    /// Enables drift compensation: mic and speaker run on different clocks, so the
+    /// output buffer slowly fills up (latency) or drains (underrun clicks). The
+    /// resample ratio is nudged so the average fill stays at `target_ms`.
+    fn with_target_fill(mut self, target_ms: Option<u16>) -> Self {
+        self.target_fill_ms = target_ms;
+        self
+    }
+
+    fn compensate_drift(&mut self) {
+        let Some(target_ms) = self.target_fill_ms else { return };
+        let target = target_ms.max(1) as f64 * self.out_sr / 1000.0;
+        let fill_frames = (self.prod.len() / self.channels) as f64;
+        self.avg_fill += (fill_frames - self.avg_fill) * FILL_SMOOTHING;
+        let error = (self.avg_fill - target) / target;
+        let correction = (error * MAX_DRIFT_CORRECTION).clamp(-MAX_DRIFT_CORRECTION, MAX_DRIFT_CORRECTION);
+        self.res.set_ratio_relative(1.0 - correction);
+    }
+
+    fn output_rate(&self) -> f64 {
+        self.out_sr
+    }
+
+    /// Switches to a rebuilt playback stream's buffer and rate.
+    fn replace_output(&mut self, prod: HeapProducer<f32>, out_sr: f64) {
+        self.prod = prod;
+        self.res = AudioResampler::new(48000.0, out_sr, self.quality, self.channels);
+        self.out_sr = out_sr;
+        self.avg_fill = 0.0;
+    }
+
+    /// Resamples one `FRAME_SIZE` frame (the chunk size the resampler is built
+    /// for) into the ring buffer. At rates like 44.1kHz the output length varies
+    /// per frame; the ring buffer absorbs that and the playback callback reads
+    /// whatever is queued.
+    fn push_frame(&mut self, frame: Vec<f32>) {
+        if let Some(res_o) = self.res.process(&frame) {
+            // Drop the whole chunk rather than part of it, keeping channels aligned.
+            if self.prod.free_len() >= res_o.len() {
+                self.prod.push_slice(&res_o);
+            }
+        }
+        self.compensate_drift();
+    }
+}
+
+fn as_frame(frame: &mut [f32]) -> &mut [f32; FRAME_SIZE] {
+    frame.try_into().expect("DSP frames are always FRAME_SIZE samples")
+}
+
+/// Floor reported for digital silence instead of -inf.
+const MIN_LEVEL_DBFS: f32 = -100.0;
+
+/// RMS level of a frame in dBFS (a full-scale sine reads -3).
+fn rms_dbfs(frame: &[f32]) -> f32 {
+    let mean_square = frame.iter().map(|s| s * s).sum::<f32>() / frame.len().max(1) as f32;
+    (10.0 * mean_square.log10()).max(MIN_LEVEL_DBFS)
+}
+
+/// Frames the gate must stay closed before the DSP goes idle (500ms).
+const IDLE_AFTER_FRAMES: usize = 50;
+/// Recent raw frames kept while idle and replayed through the processors on
+/// resume, so the noise estimate and AGC gain aren't stale on the first word.
+const PRIME_FRAMES: usize = 3;
+/// Fade-in applied when the gate opens, hiding the PTT key-press click (10ms).
+const GATE_RAMP_SAMPLES: usize = 480;
+/// Upper bound for `transmit_prebuffer_ms`, in frames (200ms). Must stay below
+/// `IDLE_AFTER_FRAMES` so the held-open tail finishes before the chain idles.
+const MAX_PREBUFFER_FRAMES: usize = 20;
+
+/// Which processing stages run. The webrtc `Processor` is by far the heaviest,
+/// so the lighter profiles exist for weak hardware.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum DspProfile {
+    /// webrtc AEC/AGC/HPF/transient suppression plus the configured denoiser.
+    Full,
+    /// RNNoise only; no webrtc `Processor` is created at all.
+    RnnoiseOnly,
+    /// No processing: the resampled input goes straight to the PTT gate.
+    None,
+}
+
+/// The shared 48kHz DSP stack: webrtc AEC/AGC, the pluggable denoiser and the PTT gate.
+/// Frames are `FRAME_SIZE` samples per channel, interleaved when stereo.
+/// While the gate stays closed the heavy stages are skipped entirely.
+struct DspChain {
+    channels: usize,
+    /// `None` unless the profile is `DspProfile::Full` and the processor could be created.
+    proc: Option<Processor>,
+    /// Stands in for the webrtc AGC when the processor failed to initialize.
+    fallback_agc: Option<SoftwareAgc>,
+    /// One per channel; denoisers only handle mono.
+    denoisers: Vec<Box<dyn Denoiser>>,
+    /// One per channel, empty when `input_eq` is `None`.
+    eqs: Vec<InputEq>,
+    eq_settings: Option<EqSettings>,
+    render_enabled: bool,
+    level_callback: Option<Arc<dyn Fn(f32) + Send + Sync>>,
+    closed_frames: usize,
+    idle_history: VecDeque<Vec<f32>>,
+    /// Processed frames awaiting output; the output lags capture by `lookahead_frames`.
+    lookahead: VecDeque<Vec<f32>>,
+    lookahead_frames: usize,
+    gate_was_open: bool,
+}
+
+impl DspChain {
+    fn new(settings: &AudioSettings) -> Self {
+        Self::with_processor(settings, Self::build_processor)
+    }
+
+    /// `new` with the webrtc `Processor` built by `build_processor`, so the
+    /// software fallback can be exercised where webrtc initializes fine.
+    fn with_processor<E: std::fmt::Display>(
+        settings: &AudioSettings,
+        build_processor: impl FnOnce(&AudioSettings) -> Result<Processor, E>,
+    ) -> Self {
+        let channels = settings.channels as usize;
+        let (proc, fallback_agc) = match settings.dsp_profile {
+            DspProfile::Full => match build_processor(settings) {
+                Ok(proc) => {
+                    println!("🎛️ DSP: webrtc audio processing");
+                    (Some(proc), None)
+                }
+                Err(e) => {
+                    let agc = settings.agc_enabled.then(SoftwareAgc::new);
+                    println!(
+                        "⚠️ webrtc audio processing unavailable ({}); DSP: {}",
+                        e,
+                        if agc.is_some() { "software gain/limiter" } else { "no gain control" }
+                    );
+                    (None, agc)
+                }
+            },
+            DspProfile::RnnoiseOnly | DspProfile::None => (None, None),
+        };
+        let webrtc_ns = proc.is_some();
+        let new_denoiser = || -> Box<dyn Denoiser> {
+            match settings.dsp_profile {
+                DspProfile::Full => match &settings.denoiser {
+                    Some(factory) => factory(),
+                    None => denoise::default_denoiser(settings.enable_extra_denoise, webrtc_ns),
+                },
+                DspProfile::RnnoiseOnly => Box::new(RnNoise::new()),
+                DspProfile::None => Box::new(Passthrough),
+            }
+        };
+
+        let lookahead_frames = Self::lookahead_frames(settings);
+
+        Self {
+            channels,
+            proc,
+            fallback_agc,
+            denoisers: (0..channels).map(|_| new_denoiser()).collect(),
+            eqs: Self::build_eqs(settings),
+            eq_settings: settings.input_eq,
+            render_enabled: settings.render_processing_enabled,
+            level_callback: settings.level_callback.clone(),
+            closed_frames: 0,
+            idle_history: VecDeque::with_capacity(PRIME_FRAMES.max(lookahead_frames)),
+            lookahead: VecDeque::with_capacity(lookahead_frames + 1),
+            lookahead_frames,
+            gate_was_open: false,
+        }
+    }
+
+    fn build_processor(settings: &AudioSettings) -> Result<Processor, webrtc_audio_processing::Error> {
+        let channels = settings.channels.into();
+        let mut proc = Processor::new(&InitializationConfig {
+            num_capture_channels: channels,
+            num_render_channels: channels,
+            ..Default::default()
+        })?;
+        proc.set_config(Self::processor_config(settings));
+        Ok(proc)
+    }
+
+    /// The webrtc `Config` for `settings`.
+    fn processor_config(settings: &AudioSettings) -> Config {
+        Config {
+            echo_cancellation: if settings.aec_enabled { Some(webrtc_audio_processing::EchoCancellation {
+                suppression_level: settings.aec_suppression_level,
+                stream_delay_ms: settings.aec_stream_delay_ms.map(i32::from),
+                enable_delay_agnostic: settings.aec_delay_agnostic,
+                enable_extended_filter: settings.aec_extended_filter,
+            }) } else { None },
+            gain_control: if settings.agc_enabled { Some(webrtc_audio_processing::GainControl {
+                mode: webrtc_audio_processing::GainControlMode::AdaptiveDigital,
+                target_level_dbfs: 3,
+                compression_gain_db: 15,
+                enable_limiter: true,
+            }) } else { None },
+            // The stock denoiser relies on the processor's own NS; a custom one replaces it.
+            noise_suppression: settings.denoiser.is_none().then_some(webrtc_audio_processing::NoiseSuppression {
+                suppression_level: settings.noise_suppression_level,
+            }),
+            enable_high_pass_filter: true,
+            enable_transient_suppressor: settings.transient_suppressor_enabled,
+            ..Default::default()
+        }
+    }
+
+    /// Applies settings that don't change the chain's layout (see
+    /// `settings_file::reload_kind`) without resetting the processors.
+    fn update(&mut self, settings: &AudioSettings) {
+        if let Some(proc) = self.proc.as_mut() {
+            proc.set_config(Self::processor_config(settings));
+        } else if settings.dsp_profile == DspProfile::Full && settings.agc_enabled != self.fallback_agc.is_some() {
+            self.fallback_agc = settings.agc_enabled.then(SoftwareAgc::new);
+        }
+        if self.eq_settings != settings.input_eq {
+            self.eq_settings = settings.input_eq;
+            self.eqs = Self::build_eqs(settings);
+        }
+        self.render_enabled = settings.render_processing_enabled;
+        self.lookahead_frames = Self::lookahead_frames(settings);
+        // `process_frame` only pops one frame per push, so a shorter prebuffer
+        // would otherwise keep the old delay until the next prime.
+        while self.lookahead.len() > self.lookahead_frames {
+            self.lookahead.pop_front();
+        }
+    }
+
+    /// Empty under `DspProfile::None`, which runs no processing at all.
+    fn build_eqs(settings: &AudioSettings) -> Vec<InputEq> {
+        if settings.dsp_profile == DspProfile::None {
+            return Vec::new();
+        }
+        let channels = settings.channels as usize;
+        settings.input_eq.iter().flat_map(|eq| (0..channels).map(move |_| InputEq::new(eq))).collect()
+    }
+
+    fn lookahead_frames(settings: &AudioSettings) -> usize {
+        (settings.transmit_prebuffer_ms as usize).div_ceil(10).min(MAX_PREBUFFER_FRAMES)
+    }
+
+    fn frame_len(&self) -> usize {
+        FRAME_SIZE * self.channels
+    }
+
+    /// Runs each channel through its own denoiser.
+    fn denoise(&mut self, frame: &mut [f32]) {
+        if self.channels == 1 {
+            self.denoisers[0].process(as_frame(frame));
+            return;
+        }
+        let mut channel = [0.0f32; FRAME_SIZE];
+        for (c, denoiser) in self.denoisers.iter_mut().enumerate() {
+            for (d, &s) in channel.iter_mut().zip(frame.iter().skip(c).step_by(self.channels)) {
+                *d = s;
+            }
+            denoiser.process(&mut channel);
+            for (s, &d) in frame.iter_mut().skip(c).step_by(self.channels).zip(channel.iter()) {
+                *s = d;
+            }
+        }
+    }
+
+    /// Processes one frame and returns what should be played/sent.
+    fn process_frame(&mut self, mut frame: Vec<f32>, is_tx: bool) -> Vec<f32> {
+        // Runs even while idle so the filter state stays continuous for `prime`.
+        for (c, eq) in self.eqs.iter_mut().enumerate() {
+            eq.process(frame.iter_mut().skip(c).step_by(self.channels));
+        }
+
+        self.closed_frames = if is_tx { 0 } else { self.closed_frames.saturating_add(1) };
+        if self.closed_frames > IDLE_AFTER_FRAMES {
+            if self.idle_history.len() == PRIME_FRAMES.max(self.lookahead_frames) {
+                self.idle_history.pop_front();
+            }
+            // Idle skips the denoiser, so the meter sees the raw mic here.
+            if let Some(cb) = &self.level_callback {
+                cb(rms_dbfs(&frame));
+            }
+            self.idle_history.push_back(frame);
+            self.gate_was_open = false;
+            return vec![0.0; self.frame_len()];
+        }
+        if !self.idle_history.is_empty() {
+            self.prime();
+        }
+
+        // 1. Process Capture (Microphone -> Clean)
+        if let Some(proc) = self.proc.as_mut() {
+            let _ = proc.process_capture_frame(&mut frame);
+        }
+
+        // 2. Denoise
+        self.denoise(&mut frame);
+        if let Some(agc) = self.fallback_agc.as_mut() {
+            agc.process(&mut frame);
+        }
+        if let Some(cb) = &self.level_callback {
+            cb(rms_dbfs(&frame));
+        }
+
+        // 3. Lookahead: delaying the output lets the gate open before the PTT press,
+        // so the first syllable isn't clipped.
+        self.lookahead.push_back(frame);
+        let frame = if self.lookahead.len() > self.lookahead_frames {
+            self.lookahead.pop_front().unwrap()
+        } else {
+            vec![0.0; self.frame_len()]
+        };
+
+        // 4. PTT Gate (held open for the lookahead after release so the delayed tail isn't cut)
+        let gate_open = self.closed_frames <= self.lookahead_frames;
+        let opening = gate_open && !self.gate_was_open;
+        self.gate_was_open = gate_open;
+        let mut output_frame = if gate_open { frame } else { vec![0.0; self.frame_len()] };
+        if opening {
+            for (i, s) in output_frame.iter_mut().take(GATE_RAMP_SAMPLES * self.channels).enumerate() {
+                *s *= (i / self.channels) as f32 / GATE_RAMP_SAMPLES as f32;
+            }
+        }
+
+        // 5. Feed Render (Speaker -> AEC Reference)
+        // In a real VoIP app, this would be the incoming network audio.
+        // Here in loopback, we feed our own output to simulate "speaker signal".
+        // Important: We must clone because process_render_frame consumes or mutates.
+        if let (true, Some(proc)) = (self.render_enabled, self.proc.as_mut()) {
+            let mut render_copy = output_frame.clone();
+            let _ = proc.process_render_frame(&mut render_copy);
+        }
+
+        output_frame
+    }
+
+    /// Runs the frames buffered while idle through the processors. Their output
+    /// refills the lookahead so a prebuffer still has audio from before the press.
+    fn prime(&mut self) {
+        let frame_len = self.frame_len();
+        while let Some(mut frame) = self.idle_history.pop_front() {
+            if let Some(proc) = self.proc.as_mut() {
+                let _ = proc.process_capture_frame(&mut frame);
+                if self.render_enabled {
+                    let mut silence = vec![0.0f32; frame_len];
+                    let _ = proc.process_render_frame(&mut silence);
+                }
+            }
+            self.denoise(&mut frame);
+            if let Some(agc) = self.fallback_agc.as_mut() {
+                agc.process(&mut frame);
+            }
+            self.lookahead.push_back(frame);
+        }
+        while self.lookahead.len() > self.lookahead_frames {
+            self.lookahead.pop_front();
+        }
+    }
+}
+
+/// Runs 48kHz `input` (`settings.channels` interleaved) through the same `DspChain`
+/// a live session uses, with PTT held down and no devices opened, so the DSP can be
+/// exercised offline. The last frame is zero-padded; the output has the input's
+/// length and, like a session, starts with the gate fade-in and lags by the
+/// transmit prebuffer.
+fn process_audio_frames(input: &[f32], settings: &AudioSettings) -> Vec<f32> {
+    let mut chain = DspChain::new(settings);
+    let frame_len = chain.frame_len();
+    let mut out = Vec::with_capacity(input.len().next_multiple_of(frame_len));
+    for chunk in input.chunks(frame_len) {
+        let mut frame = chunk.to_vec();
+        frame.resize(frame_len, 0.0);
+        out.extend(chain.process_frame(frame, true));
+    }
+    out.truncate(input.len());
+    out
+}
+
+/// `process_audio_frames` on a raw file of 48kHz f32 little-endian samples
+/// (`settings.channels` interleaved), written to `output` in the same format.
+fn process_file(input: &str, output: &str, settings: &AudioSettings) -> anyhow::Result<()> {
+    let bytes = std::fs::read(input)?;
+    let samples: Vec<f32> = bytes.chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect();
+    let processed = process_audio_frames(&samples, settings);
+    std::fs::write(output, processed.iter().flat_map(|s| s.to_le_bytes()).collect::<Vec<u8>>())?;
+    println!("✅ Processed {:.1}s into {}", samples.len() as f64 / 48000.0 / settings.channels as f64, output);
+    Ok(())
+}
+
+/// How often the rate watcher re-reads the devices' default configs.
+const RATE_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The open streams' sample rates in Hz, shared with the owning `AudioSession`.
+/// An output rate of 0 means the session has no local output.
+struct SampleRates {
+    input: AtomicU32,
+    output: AtomicU32,
+}
+
+/// Streams that need rebuilding, raised by their cpal error callbacks or by the
+/// rate watcher, and handled on the session owner's thread by `rebuild_stale_streams`.
+#[derive(Default)]
+struct StreamHealth {
+    input_stale: AtomicBool,
+    output_stale: AtomicBool,
+}
+
+impl StreamHealth {
+    /// A cpal error callback that logs and marks the stream for rebuilding.
+    fn on_error(self: &Arc<Self>, output: bool) -> impl FnMut(cpal::StreamError) + Send + 'static {
+        let health = self.clone();
+        move |e| {
+            println!("⚠️ {} stream error: {}", if output { "Output" } else { "Input" }, e);
+            health.flag(output);
+        }
+    }
+
+    fn flag(&self, output: bool) {
+        let stale = if output { &self.output_stale } else { &self.input_stale };
+        stale.store(true, Ordering::Relaxed);
+    }
+}
+
+/// A rebuilt stream's buffer, handed to the DSP thread so it can rebuild the
+/// matching resampler without restarting the DSP chain.
+enum StreamSwap {
+    Input { cons: HeapConsumer<f32>, sample_rate: f64 },
+    Output { prod: HeapProducer<f32>, sample_rate: f64 },
+}
+
+/// PipeWire/PulseAudio can switch a device's rate at runtime when another app
+/// opens it. Every `RATE_CHECK_INTERVAL` this compares the devices' default rates
+/// with the open streams' and flags a mismatch. It runs on its own thread since
+/// the query opens the PCM on ALSA, which is too slow for the DSP loop.
+fn spawn_rate_watcher(
+    in_device: cpal::Device,
+    out_device: Option<cpal::Device>,
+    rates: Arc<SampleRates>,
+    health: Arc<StreamHealth>,
+    running: Arc<AtomicBool>,
+) -> std::thread::JoinHandle<()> {
+    std::thread::spawn(move || {
+        let mut last_check = Instant::now();
+        while running.load(Ordering::Relaxed) {
+            std::thread::sleep(Duration::from_millis(100));
+            if last_check.elapsed() < RATE_CHECK_INTERVAL {
+                continue;
+            }
+            last_check = Instant::now();
+
+            if let Ok(c) = in_device.default_input_config() {
+                let (old, new) = (rates.input.load(Ordering::Relaxed), c.sample_rate().0);
+                if new != old {
+                    println!("🔁 Input rate changed {}Hz -> {}Hz, rebuilding the input stream", old, new);
+                    health.flag(false);
+                }
+            }
+            if let Some(Ok(c)) = out_device.as_ref().map(|d| d.default_output_config()) {
+                let (old, new) = (rates.output.load(Ordering::Relaxed), c.sample_rate().0);
+                if new != old {
+                    println!("🔁 Output rate changed {}Hz -> {}Hz, rebuilding the output stream", old, new);
+                    health.flag(true);
+                }
+            }
+        }
+    })
+}
+
+/// Output peak below which a frame counts as silence (-60 dBFS).
+const SILENCE_THRESHOLD: f32 = 0.001;
+
+/// Counts consecutive silent output frames (gated frames are all zeros).
+struct IdleTracker {
+    timeout_frames: usize,
+    silent_frames: usize,
+}
+
+impl IdleTracker {
+    /// Rounds `timeout` up to whole 10ms frames, so even a sub-frame timeout fires.
+    fn new(timeout: Duration) -> Self {
+        Self { timeout_frames: (timeout.as_millis().div_ceil(10) as usize).max(1), silent_frames: 0 }
+    }
+
+    /// Returns true exactly once, on the frame where the timeout is crossed.
+    /// Any non-silent frame resets the count.
+    fn update(&mut self, frame: &[f32]) -> bool {
+        if frame.iter().any(|s| s.abs() >= SILENCE_THRESHOLD) {
+            self.silent_frames = 0;
+            return false;
+        }
+        self.silent_frames += 1;
+        self.silent_frames == self.timeout_frames
+    }
+}
+
+impl AudioSession {
+    /// True once `idle_timeout` elapsed without audio; the owner should drop the session.
+    fn idle_timed_out(&self) -> bool {
+        self.idle_timed_out.load(Ordering::Relaxed)
+    }
+
+    /// Sends the processed mic to a LiveKit `NativeAudioSource`, which must be
+    /// created at 48kHz with `settings.channels`; frames are delivered on
+    /// `runtime`. This is the constructor to use for a call.
+    fn create_for_livekit(
+        in_id: &str,
+        state: Arc<GlobalAudioState>,
+        settings: AudioSettings,
+        source: Arc<NativeAudioSource>,
+        runtime: &tokio::runtime::Handle,
+    ) -> anyhow::Result<Self> {
+        check_settings(&settings)?;
+        if source.sample_rate() != 48000 || source.num_channels() != settings.channels as u32 {
+            return Err(anyhow::anyhow!(
+                "NativeAudioSource must be 48000Hz with {} channel(s), got {}Hz with {}",
+                settings.channels,
+                source.sample_rate(),
+                source.num_channels()
+            ));
+        }
+        Self::start(in_id, state, settings, FrameSink::livekit(source, runtime), None, Arc::new(StreamHealth::default()))
+    }
+
+    /// Plays the processed mic back on the default output device. Useful for
+    /// testing the chain locally; calls should use `create_for_livekit`.
+    fn create(in_id: &str, state: Arc<GlobalAudioState>, settings: AudioSettings) -> anyhow::Result<Self> {
+        check_settings(&settings)?;
+        let host = cpal::default_host();
+        let out_device = resolve_output_device(&host, "default")?;
+
+        let channels = settings.channels as usize;
+        let health = Arc::new(StreamHealth::default());
+        let channel_map = settings.output_channel_map.clone();
+        let (prod_out, out_stream, out_sr) = open_playback(&out_device, channel_map.as_deref(), channels, &health)?;
+        let playback = PlaybackResampler::new(prod_out, out_sr, settings.resampler_quality, channels)
+            .with_target_fill(settings.output_target_fill_ms);
+
+        let output = SessionOutput { device: out_device, stream: Some(out_stream), channel_map };
+        Self::start(in_id, state, settings, FrameSink::Loopback(playback), Some(output), health)
+    }
+
+    /// Opens the input and runs the DSP thread into `sink`. `output` is the
+    /// loopback device and stream, kept alive (and rebuilt) with the session.
+    fn start(
+        in_id: &str,
+        state: Arc<GlobalAudioState>,
+        settings: AudioSettings,
+        mut sink: FrameSink,
+        output: Option<SessionOutput>,
+        health: Arc<StreamHealth>,
+    ) -> anyhow::Result<Self> {
+        let host = cpal::default_host();
+        let in_device = open_input_device(&host, in_id, settings.exclusivity)?;
+
+        let channels = settings.channels as usize;
+        let dropped_input = Arc::new(AtomicU64::new(0));
+        let (in_stream, in_sr, cons_in) = build_capture_stream(&in_device, channels, dropped_input.clone(), health.on_error(false))
+            .map_err(|e| exclusive_hint(e, settings.exclusivity))?;
+        let mut overflow = OverflowMonitor::new("Capture", dropped_input.clone(), in_sr, channels);
+
+        let out_sr = match &sink {
+            FrameSink::Loopback(playback) => playback.output_rate() as u32,
+            FrameSink::LiveKit { .. } => 0,
+        };
+        let sample_rates = Arc::new(SampleRates { input: AtomicU32::new(in_sr as u32), output: AtomicU32::new(out_sr) });
+
+        let (swaps, swap_rx) = mpsc::channel();
+        let (settings_updates, settings_rx) = mpsc::channel::<AudioSettings>();
+        let idle_timed_out = Arc::new(AtomicBool::new(false));
+        let thread_idle = idle_timed_out.clone();
+        let running = Arc::new(AtomicBool::new(true));
+        let thread_running = running.clone();
+
+        // Start the streams first so a failure returns before there is a thread to stop.
+        in_stream.play()?;
+        if let Some(out_stream) = output.as_ref().and_then(|o| o.stream.as_ref()) {
+            out_stream.play()?;
+        }
+        let dsp_thread = std::thread::spawn(move || {
+            let mut chain = DspChain::new(&settings);
+            let mut capture = CaptureResampler::new(cons_in, in_sr, settings.resampler_quality, channels);
+            // Only the local speaker is normalized.
+            let plays_locally = matches!(sink, FrameSink::Loopback(_));
+            let new_normalizer = |target: Option<f32>| target.filter(|_| plays_locally).map(|t| LoudnessNormalizer::new(t, channels));
+            let mut normalizer = new_normalizer(settings.output_loudness_target_lufs);
+            let mut idle = settings.idle_timeout.map(IdleTracker::new);
+
+            while thread_running.load(Ordering::Relaxed) {
+                for swap in swap_rx.try_iter() {
+                    match (swap, &mut sink) {
+                        (StreamSwap::Input { cons, sample_rate }, _) => {
+                            capture.replace_input(cons, sample_rate);
+                            overflow.set_sample_rate(sample_rate);
+                        }
+                        (StreamSwap::Output { prod, sample_rate }, FrameSink::Loopback(playback)) => {
+                            playback.replace_output(prod, sample_rate)
+                        }
+                        (StreamSwap::Output { .. }, FrameSink::LiveKit { .. }) => {}
+                    }
+                }
+                for update in settings_rx.try_iter() {
+                    chain.update(&update);
+                    let target = update.output_loudness_target_lufs.filter(|_| plays_locally);
+                    if target != normalizer.as_ref().map(LoudnessNormalizer::target_lufs) {
+                        normalizer = new_normalizer(target);
+                    }
+                }
+                overflow.poll();
+                capture.poll();
+                state.flush_transmit_events();
+                while let Some(frame) = capture.next_frame() {
+                    let is_tx = state.is_transmitting.load(Ordering::Relaxed);
+                    let mut out = chain.process_frame(frame, is_tx);
+                    if let Some(n) = normalizer.as_mut() {
+                        n.process(&mut out);
+                    }
+                    if idle.as_mut().is_some_and(|t| t.update(&out)) {
+                        thread_idle.store(true, Ordering::Relaxed);
+                        if let Some(cb) = &settings.on_idle_timeout {
+                            cb();
+                        }
+                    }
+                    sink.push_frame(out);
+                }
+                std::thread::sleep(Duration::from_millis(1));
+            }
+        });
+
+        let rate_watcher = spawn_rate_watcher(
+            in_device.clone(),
+            output.as_ref().map(|o| o.device.clone()),
+            sample_rates.clone(),
+            health.clone(),
+            running.clone(),
+        );
+        Ok(Self {
+            in_device,
+            in_stream: Some(in_stream),
+            output,
+            channels,
+            health,
+            swaps,
+            settings_updates,
+            idle_timed_out,
+            dropped_input,
+            sample_rates,
+            running,
+            dsp_thread: Some(dsp_thread),
+            rate_watcher: Some(rate_watcher),
+        })
+    }
+
+    /// Reopens streams flagged by their error callback or the rate watcher at the
+    /// device's current config, keeping the DSP chain running. Call it regularly
+    /// from the session's owner; on error the session should be reopened.
+    fn rebuild_stale_streams(&mut self) -> anyhow::Result<()> {
+        if self.health.input_stale.swap(false, Ordering::Relaxed) {
+            // Close the old stream first; some backends only allow one open handle.
+            self.in_stream = None;
+            let (stream, sr, cons) =
+                build_capture_stream(&self.in_device, self.channels, self.dropped_input.clone(), self.health.on_error(false))?;
+            stream.play()?;
+            self.in_stream = Some(stream);
+            self.sample_rates.input.store(sr as u32, Ordering::Relaxed);
+            let _ = self.swaps.send(StreamSwap::Input { cons, sample_rate: sr });
+            println!("✅ Input stream reopened at {}Hz", sr);
+        }
+        if let Some(output) = self.output.as_mut() {
+            if self.health.output_stale.swap(false, Ordering::Relaxed) {
+                output.stream = None;
+                let (prod, stream, sr) = open_playback(&output.device, output.channel_map.as_deref(), self.channels, &self.health)?;
+                stream.play()?;
+                output.stream = Some(stream);
+                self.sample_rates.output.store(sr as u32, Ordering::Relaxed);
+                let _ = self.swaps.send(StreamSwap::Output { prod, sample_rate: sr });
+                println!("✅ Output stream reopened at {}Hz", sr);
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies a settings change that `settings_file::reload_kind` classed as
+    /// live (processing parameters, loudness target, prebuffer) to the running
+    /// DSP thread. Other changes need the session reopened.
+    fn update_settings(&self, settings: &AudioSettings) {
+        let _ = self.settings_updates.send(settings.clone());
+    }
+
+    fn dropped_input_samples(&self) -> u64 {
+        self.dropped_input.load(Ordering::Relaxed)
+    }
+
+    /// The capture device's current rate in Hz (follows runtime rate changes).
+    fn input_sample_rate(&self) -> u32 {
+        self.sample_rates.input.load(Ordering::Relaxed)
+    }
+
+    /// The loopback output device's current rate in Hz; `None` for a LiveKit
+    /// session, whose sink always runs at 48kHz.
+    fn output_sample_rate(&self) -> Option<u32> {
+        match self.sample_rates.output.load(Ordering::Relaxed) {
+            0 => None,
+            sr => Some(sr),
+        }
+    }
+
+    /// Stops the DSP thread and waits for it, then closes the streams.
+    /// Dropping the session does the same.
+    fn shutdown(mut self) {
+        self.stop_dsp_thread();
+    }
+
+    fn stop_dsp_thread(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(handle) = self.dsp_thread.take() {
+            if handle.join().is_err() {
+                println!("⚠️ DSP thread panicked");
+            }
+        }
+        if let Some(handle) = self.rate_watcher.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for AudioSession {
+    fn drop(&mut self) {
+        self.stop_dsp_thread();
+    }
+}
+
+// --- INPUT LISTENER ---
+
+/// The left/right partner of a modifier key, if `key` is one.
+fn modifier_partner(key: Key) -> Option<Key> {
+    match key {
+        Key::ControlLeft => Some(Key::ControlRight),
+        Key::ControlRight => Some(Key::ControlLeft),
+        Key::ShiftLeft => Some(Key::ShiftRight),
+        Key::ShiftRight => Some(Key::ShiftLeft),
+        Key::MetaLeft => Some(Key::MetaRight),
+        Key::MetaRight => Some(Key::MetaLeft),
+        Key::Alt => Some(Key::AltGr),
+        Key::AltGr => Some(Key::Alt),
+        _ => None,
+    }
+}
+
+/// Whether an event for `key` should drive a PTT bound to `target`. Some
+/// platforms report the other side of a modifier than the one pressed, so
+/// `match_both_modifiers` accepts either side.
+fn ptt_key_matches(target: Key, key: Key, match_both_modifiers: bool) -> bool {
+    key == target || (match_both_modifiers && modifier_partner(target) == Some(key))
+}
+
+/// Every key a PTT can be bound to by name, i.e. everything but `Key::Unknown`.
+const NAMED_KEYS: &[Key] = &[
+    Key::Alt, Key::AltGr, Key::Backspace, Key::CapsLock, Key::ControlLeft, Key::ControlRight, Key::Delete,
+    Key::DownArrow, Key::End, Key::Escape, Key::F1, Key::F2, Key::F3, Key::F4, Key::F5, Key::F6, Key::F7,
+    Key::F8, Key::F9, Key::F10, Key::F11, Key::F12, Key::Home, Key::LeftArrow, Key::MetaLeft, Key::MetaRight,
+    Key::PageDown, Key::PageUp, Key::Return, Key::RightArrow, Key::ShiftLeft, Key::ShiftRight, Key::Space,
+    Key::Tab, Key::UpArrow, Key::PrintScreen, Key::ScrollLock, Key::Pause, Key::NumLock, Key::BackQuote,
+    Key::Num1, Key::Num2, Key::Num3, Key::Num4, Key::Num5, Key::Num6, Key::Num7, Key::Num8, Key::Num9,
+    Key::Num0, Key::Minus, Key::Equal, Key::KeyQ, Key::KeyW, Key::KeyE, Key::KeyR, Key::KeyT, Key::KeyY,
+    Key::KeyU, Key::KeyI, Key::KeyO, Key::KeyP, Key::LeftBracket, Key::RightBracket, Key::KeyA, Key::KeyS,
+    Key::KeyD, Key::KeyF, Key::KeyG, Key::KeyH, Key::KeyJ, Key::KeyK, Key::KeyL, Key::SemiColon, Key::Quote,
+    Key::BackSlash, Key::IntlBackslash, Key::KeyZ, Key::KeyX, Key::KeyC, Key::KeyV, Key::KeyB, Key::KeyN,
+    Key::KeyM, Key::Comma, Key::Dot, Key::Slash, Key::Insert, Key::KpReturn, Key::KpMinus, Key::KpPlus,
+    Key::KpMultiply, Key::KpDivide, Key::Kp0, Key::Kp1, Key::Kp2, Key::Kp3, Key::Kp4, Key::Kp5, Key::Kp6,
+    Key::Kp7, Key::Kp8, Key::Kp9, Key::KpDelete, Key::Function,
+];
+
+/// Parses a key by its rdev name ("ControlLeft", "KeyV", "F5"), ignoring case.
+fn parse_key(name: &str) -> Option<Key> {
+    NAMED_KEYS.iter().copied().find(|k| format!("{:?}", k).eq_ignore_ascii_case(name.trim()))
+}
+
+/// Key auto-repeat can arrive as release/press pairs with (near) identical
+/// timestamps; a press this soon after a release is treated as a repeat.
+const AUTO_REPEAT_GAP: Duration = Duration::from_millis(30);
+
+/// Toggle mode only flips on a fresh press, not on auto-repeat while held.
+#[derive(Default)]
+struct ToggleKey {
+    held: bool,
+    last_release: Option<SystemTime>,
+}
+
+impl ToggleKey {
+    /// Records a press of the PTT key; true if it should flip the transmit state.
+    fn press(&mut self, at: SystemTime) -> bool {
+        let repeat = self.held
+            || self.last_release.and_then(|t| at.duration_since(t).ok()).is_some_and(|gap| gap < AUTO_REPEAT_GAP);
+        self.held = true;
+        !repeat
+    }
+
+    fn release(&mut self, at: SystemTime) {
+        self.held = false;
+        self.last_release = Some(at);
+    }
+}
+
+fn print_transmit_state(transmitting: bool) {
+    print!("{} ", if transmitting { "🎤" } else { "🔇" });
+    use std::io::Write;
+    let _ = std::io::stdout().flush();
+}
+
+fn start_input_listener(state: Arc<GlobalAudioState>, settings: Arc<Mutex<AudioSettings>>) {
+    std::thread::spawn(move || {
+        println!("⌨️  Global Input Listener started (rdev)");
+
+        let mut toggle_key = ToggleKey::default();
+
+        // This callback will be called for every input event
+        let callback = move |event: Event| {
+            state.flush_transmit_events();
+
+            // A pending `capture_next_key` takes the press before anything else.
+            if let EventType::KeyPress(key) = event.event_type {
+                if state.deliver_captured_key(key) {
+                    return;
+                }
+            }
+
+            let (target_key, enabled, match_both, mode) = {
+                let s = settings.lock();
+                (s.ptt_key, s.ptt_enabled, s.ptt_match_both_modifiers, s.ptt_mode)
+            };
+
+            if !enabled {
+                state.set_transmitting(true);
+                return;
+            }
+
+            match (event.event_type, mode) {
+                (EventType::KeyPress(key), PttMode::Hold) if ptt_key_matches(target_key, key, match_both) => {
+                    state.set_transmitting(true);
+                },
+                (EventType::KeyRelease(key), PttMode::Hold) if ptt_key_matches(target_key, key, match_both) => {
+                    state.set_transmitting(false);
+                },
+                // `press` records every press of the key; only fresh ones toggle.
+                (EventType::KeyPress(key), PttMode::Toggle)
+                    if ptt_key_matches(target_key, key, match_both) && toggle_key.press(event.time) =>
+                {
+                    state.toggle_transmitting();
+                },
+                (EventType::KeyRelease(key), PttMode::Toggle) if ptt_key_matches(target_key, key, match_both) => {
+                    toggle_key.release(event.time);
+                },
+                _ => {}
+            }
+        };
+
+        if let Err(error) = listen(callback) {
+            println!("❌ Input Error: {:?}", error);
+        }
+    });
+}
+
+/// Prints the input level as a bar graph on one line, ten times a second.
+fn vu_meter() -> Arc<dyn Fn(f32) + Send + Sync> {
+    let frames = std::sync::atomic::AtomicUsize::new(0);
+    Arc::new(move |db: f32| {
+        if !frames.fetch_add(1, Ordering::Relaxed).is_multiple_of(10) {
+            return;
+        }
+        // -60..0 dBFS across 40 columns.
+        let width = (((db + 60.0) / 60.0).clamp(0.0, 1.0) * 40.0) as usize;
+        print!("\r🎚️  [{:<40}] {:>6.1} dBFS", "#".repeat(width), db);
+        use std::io::Write;
+        let _ = std::io::stdout().flush();
+    })
+}
+
+// --- LIVEKIT ---
+
+/// A joined LiveKit room publishing one microphone track that is fed through `source`.
+struct LiveKitCall {
+    source: Arc<NativeAudioSource>,
+    _room: Room,
+    runtime: tokio::runtime::Runtime,
+}
+
+impl LiveKitCall {
+    /// Validates the credentials before connecting, so a bad URL or an expired
+    /// token is reported as such rather than as an opaque connection error.
+    fn join(url: &str, token: &str, channels: u16) -> anyhow::Result<Self> {
+        credentials::validate_livekit_credentials(url, token)?;
+        let runtime = tokio::runtime::Runtime::new()?;
+        let source = Arc::new(NativeAudioSource::new(AudioSourceOptions::default(), 48000, channels.into(), 0));
+        let room = runtime.block_on(async {
+            let (room, _events) = Room::connect(url, token, RoomOptions::default()).await?;
+            let track = LocalAudioTrack::create_audio_track("microphone", RtcAudioSource::Native((*source).clone()));
+            let options = TrackPublishOptions { source: TrackSource::Microphone, ..Default::default() };
+            room.local_participant().publish_track(LocalTrack::Audio(track), options).await?;
+            anyhow::Ok(room)
+        })?;
+        Ok(Self { source, _room: room, runtime })
+    }
+}
+
+/// Runs an `AudioMixerSession` until stdin closes, taking per-input gain and
+/// mute commands from it.
+fn run_mixer(in_ids: &[&str], state: Arc<GlobalAudioState>, settings: AudioSettings) -> anyhow::Result<()> {
+    let mixer = mixer::AudioMixerSession::create(in_ids, state, settings)
+        .inspect_err(|e| println!("❌ Cannot start the mixer: {:?}", e))?;
+    println!("🎛️ Mixing {} inputs. Commands: gain <input> <gain>, mute <input>, unmute <input>; Ctrl-D stops.", in_ids.len());
+    for line in std::io::stdin().lines() {
+        let line = line?;
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let input = |word: &str| word.parse::<usize>().ok().filter(|&i| i < in_ids.len());
+        match words.as_slice() {
+            ["gain", i, gain] => match (input(i), gain.parse::<f32>()) {
+                (Some(i), Ok(gain)) => mixer.set_input_gain(i, gain),
+                _ => println!("⚠️ Usage: gain <input 0-{}> <gain>", in_ids.len() - 1),
+            },
+            [cmd @ ("mute" | "unmute"), i] => match input(i) {
+                Some(i) => mixer.set_input_muted(i, *cmd == "mute"),
+                None => println!("⚠️ No input {}", i),
+            },
+            [] => {}
+            _ => println!("⚠️ Unknown command: {}", line),
+        }
+    }
+    Ok(())
+}
+
+/// The command-line arguments following `flag`, if it was given.
+fn args_after(flag: &str) -> Option<Vec<String>> {
+    let mut args = std::env::args().skip_while(|a| a != flag);
+    args.next()?;
+    Some(args.collect())
+}
+
+fn main() -> anyhow::Result<()> {
+    #[cfg(target_os = "linux")]
+    unsafe { libc::close(2); }
+    env_logger::init();
+    dotenv::dotenv().ok();
+
+    // `--measure-latency` reports the speaker-to-mic delay for `aec_stream_delay_ms` and exits.
+    if std::env::args().any(|a| a == "--measure-latency") {
+        return match latency::measure_loopback_latency("default", "default") {
+            Ok(m) => {
+                println!("📏 Speaker-to-mic delay {:.1}ms (confidence {:.2})", m.delay_ms, m.confidence);
+                println!("Set aec_stream_delay_ms = {}", m.delay_ms.round() as u16);
+                Ok(())
+            }
+            Err(e) => {
+                println!("❌ Latency measurement failed: {}", e);
+                Err(e)
+            }
+        };
+    }
+
+    // `--process in.f32 out.f32` runs a raw 48kHz recording through the DSP chain
+    // offline (with NEANDERTAL_SETTINGS_FILE applied, if set) and exits.
+    if let Some(args) = args_after("--process") {
+        let [input, output, ..] = args.as_slice() else {
+            println!("Usage: --process <in.f32> <out.f32>");
+            return Err(anyhow::anyhow!("--process needs an input and an output file"));
+        };
+        let mut settings = AudioSettings::default();
+        if let Ok(path) = std::env::var("NEANDERTAL_SETTINGS_FILE") {
+            settings = settings_file::apply_settings_json(&settings, &std::fs::read_to_string(path)?)?;
+        }
+        return process_file(input, output, &settings).inspect_err(|e| println!("❌ Processing failed: {}", e));
+    }
+
+    let host = cpal::default_host();
+    let settings = Arc::new(Mutex::new(AudioSettings::default()));
+    if std::env::var_os("NEANDERTAL_VU_METER").is_some() {
+        settings.lock().level_callback = Some(vu_meter());
+    }
+    
+    // --- SHARED STATE & INPUT HANDLING ---
+    let global_state = Arc::new(GlobalAudioState::new(true)); // Start transmitting by default
+
+    start_input_listener(global_state.clone(), settings.clone());
+
+    let transmit_events = global_state.subscribe_transmit_events();
+    std::thread::spawn(move || {
+        for event in transmit_events {
+            print_transmit_state(event.transmitting);
+        }
+    });
+
+    // Optional live-editable settings; device and format changes reopen the session.
+    let settings_reloads = std::env::var("NEANDERTAL_SETTINGS_FILE").ok().and_then(|path| {
+        settings_file::watch_settings_file(&path, settings.clone())
+            .inspect_err(|e| println!("⚠️ Cannot watch settings file {}: {}", path, e))
+            .ok()
+    });
+
+    // `--bind-ptt` picks the push-to-talk key by pressing it.
+    if std::env::args().any(|a| a == "--bind-ptt") {
+        println!("⌨️  Press the key to use for push-to-talk...");
+        match global_state.capture_next_key(Duration::from_secs(10)) {
+            Some(key) => {
+                println!("✅ Push-to-talk key: {:?}", key);
+                settings.lock().ptt_key = key;
+            }
+            None => println!("⚠️ No key pressed, keeping {:?}", settings.lock().ptt_key),
+        }
+    }
+
+    // `--mix id1,id2,...` mixes several inputs to the speaker instead of running a session.
+    if let Some(args) = args_after("--mix") {
+        let Some(ids) = args.first() else {
+            println!("Usage: --mix <input id>,<input id>,...");
+            return Err(anyhow::anyhow!("--mix needs a comma-separated list of input ids"));
+        };
+        let current_settings = settings.lock().clone();
+        return run_mixer(&ids.split(',').collect::<Vec<_>>(), global_state, current_settings);
+    }
+
+    // With LIVEKIT_URL and LIVEKIT_TOKEN set the processed mic is published to a
+    // room; otherwise it is played back locally for testing.
+    let call = match (std::env::var("LIVEKIT_URL"), std::env::var("LIVEKIT_TOKEN")) {
+        (Ok(url), Ok(token)) => match LiveKitCall::join(&url, &token, settings.lock().channels) {
+            Ok(call) => {
+                println!("📡 Joined LiveKit room, publishing the microphone.");
+                Some(call)
+            }
+            Err(e) => {
+                // stderr is closed on Linux, so say why before bailing out.
+                println!("❌ Cannot join LiveKit room: {}", e);
+                return Err(e);
+            }
+        },
+        _ => None,
+    };
+
+    println!("\n=== NEANDERTAL VOIP CORE AUDIO DEVICE LIST ===");
+    let inputs = get_professional_device_list(&host);
+    for (i, dev) in inputs.iter().enumerate() { println!("{}. {}", i, dev.display_name); }
+    println!("==============================\n");
+
+    // Prioritize "System Default" devices for better compatibility
+    let _alt_mic = inputs.iter()
+        .find(|d| d.id != "default" && d.display_name.contains("System Default") && (d.display_name.contains("Dahili") || d.display_name.contains("USB")))
+        .or_else(|| inputs.iter().find(|d| d.id != "default" && (d.display_name.contains("Dahili") || d.display_name.contains("USB"))))
+        .cloned()
+        .unwrap_or_else(|| inputs.last().cloned().unwrap());
+
+    // START WITH DEFAULT SETTINGS
+    // settings.lock().input_device_id is "default" by the Default impl.
+    
+    // Select initial device based on settings (which is "default")
+    // If we wanted to persist settings, we would load them here.
+
+    let mut _session: Option<AudioSession> = None;
+    let mut last_id = String::new();
+    let mut default_watcher = DefaultDeviceWatcher::new(&host);
+    
+    // Initial start
+    // We let the loop handle the first start to reuse logic
+
+    loop {
+        // Checked before the snapshot below so a reload is never opened with stale settings.
+        let reloads: Vec<_> = settings_reloads.iter().flat_map(|rx| rx.try_iter()).collect();
+        if reloads.contains(&SettingsReload::Restart) {
+            println!("🔄 Settings changed, reopening...");
+            last_id.clear();
+        } else if let (false, Some(session)) = (reloads.is_empty(), &_session) {
+            session.update_settings(&settings.lock());
+        }
+
+        let (current_id, current_settings) = {
+            let s = settings.lock();
+            (s.input_device_id.clone(), s.clone())
+        };
+
+        // Sessions on "default" follow the OS default (e.g. a headset that was just plugged in).
+        // The output side is always opened on "default", so an output change affects every
+        // session that plays locally; LiveKit sessions have no output.
+        if let Some(changed) = default_watcher.poll(&host) {
+            if default_change_needs_reopen(&current_id, call.is_none(), changed) {
+                println!("🔀 System default device changed, reopening...");
+                last_id.clear();
+            }
+        }
+
+        if let Some(Err(e)) = _session.as_mut().map(|s| s.rebuild_stale_streams()) {
+            println!("❌ Cannot reopen a stream: {:?}, reopening the session...", e);
+            _session = None;
+            last_id.clear();
+        }
+
+        if _session.as_ref().is_some_and(|s| s.idle_timed_out()) {
+            println!("💤 Idle timeout reached, closing session.");
+            _session = None;
+        }
+
+        if current_id != last_id {
+            if let Some(old) = _session.take() {
+                println!("🛑 Closing old session ({} input samples dropped)...", old.dropped_input_samples());
+                old.shutdown();
+            }
+            
+            // Wait for device to be released by OS/ALSA
+            std::thread::sleep(Duration::from_millis(1000));
+            
+            let opened = match &call {
+                Some(call) => AudioSession::create_for_livekit(
+                    &current_id,
+                    global_state.clone(),
+                    current_settings,
+                    call.source.clone(),
+                    call.runtime.handle(),
+                ),
+                None => AudioSession::create(&current_id, global_state.clone(), current_settings),
+            };
+            match opened {
+                Ok(s) => {
+                    println!("✅ Active ({}Hz in, {}Hz out).", s.input_sample_rate(), s.output_sample_rate().unwrap_or(48000));
+                    _session = Some(s);
+                    last_id = current_id;
+                }
+                Err(e) => { 
+                    println!("❌ Failed to open '{}': {:?}", current_id, e);
+                    // Don't update last_id so it retries or allows UI to show error state
+                    // Actually, if we fail, we might want to stay in "failed" state or fallback.
+                    // For now, let's update last_id to prevent infinite retry loop on same ID
+                    last_id = current_id; 
+                }
+            }
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // --- DEVICE RESOLUTION ---
+
+    fn fake_devices() -> Result<Vec<(String, u32)>, AudioError> {
+        Ok(vec![
+            ("sysdefault:CARD=PCH".to_string(), 1),
+            ("plughw:CARD=Microphone,DEV=0".to_string(), 2),
+            ("plughw:CARD=PCH,DEV=0".to_string(), 3),
+        ])
+    }
+
+    #[test]
+    fn default_id_skips_the_device_list() {
+        let listed = || -> Result<Vec<(String, u32)>, AudioError> { panic!("default must not enumerate devices") };
+        assert_eq!(resolve_device("default", "input", || Some(7), listed).unwrap(), 7);
+        assert!(matches!(resolve_device("default", "input", || None::<u32>, listed), Err(AudioError::NoDefaultDevice("input"))));
+    }
+
+    #[test]
+    fn exact_name_wins() {
+        assert_eq!(resolve_device("plughw:CARD=PCH,DEV=0", "input", || None, fake_devices).unwrap(), 3);
+    }
+
+    #[test]
+    fn card_fallback_finds_another_name_for_the_same_card() {
+        assert_eq!(resolve_device("sysdefault:CARD=Microphone", "input", || None, fake_devices).unwrap(), 2);
+    }
+
+    #[test]
+    fn unknown_device_is_not_found() {
+        let err = resolve_device("sysdefault:CARD=Headset", "input", || None, fake_devices).unwrap_err();
+        assert!(matches!(err, AudioError::DeviceNotFound(id) if id == "sysdefault:CARD=Headset"));
+    }
+
+    // --- CAPTURE RESAMPLING ---
+
+    /// Pushes half a second of a 1kHz sine at `sr` and returns the 48kHz frames it yields.
+    fn capture_tone(capture: &mut CaptureResampler, prod: &mut HeapProducer<f32>, sr: f64) -> Vec<f32> {
+        for i in 0..(sr / 2.0) as usize {
+            prod.push((2.0 * std::f64::consts::PI * 1000.0 * i as f64 / sr).sin() as f32).unwrap();
+        }
+        let mut out = Vec::new();
+        for _ in 0..1000 {
+            capture.poll();
+            while let Some(frame) = capture.next_frame() {
+                out.extend(frame);
+            }
+        }
+        out
+    }
+
+    /// Frequency of a 48kHz tone from its rising zero crossings, skipping the resampler's start-up.
+    fn tone_hz(samples: &[f32]) -> f64 {
+        let settled = &samples[4800..];
+        let crossings = settled.windows(2).filter(|w| w[0] < 0.0 && w[1] >= 0.0).count();
+        crossings as f64 * 48000.0 / settled.len() as f64
+    }
+
+    #[test]
+    fn replaced_input_is_resampled_at_its_new_rate() {
+        let (mut prod, cons) = HeapRb::<f32>::new(96000).split();
+        let mut capture = CaptureResampler::new(cons, 44100.0, ResamplerQuality::High, 1);
+        let before = capture_tone(&mut capture, &mut prod, 44100.0);
+        assert!((tone_hz(&before) - 1000.0).abs() < 10.0, "{}Hz", tone_hz(&before));
+
+        // The device switched to 96kHz and its stream was rebuilt.
+        let (mut prod, cons) = HeapRb::<f32>::new(96000).split();
+        capture.replace_input(cons, 96000.0);
+        let after = capture_tone(&mut capture, &mut prod, 96000.0);
+        assert!((tone_hz(&after) - 1000.0).abs() < 10.0, "{}Hz", tone_hz(&after));
+        assert!((22000..=24000).contains(&after.len()), "{} samples for 0.5s", after.len());
+    }
+
+    // --- OUTPUT ROUTING ---
+
+    /// What each of `channels` output channels plays for a mono sample of 1.0.
+    fn mono_fill(channels: usize, channel_map: Option<&[usize]>) -> Vec<f32> {
+        output_routing(channels, channel_map, 1).iter().map(|r| r.sample(&[1.0, 0.0])).collect()
+    }
+
+    #[test]
+    fn six_channel_output_fills_front_left_and_right_only() {
+        assert_eq!(mono_fill(6, None), [1.0, 1.0, 0.0, 0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn six_channel_output_follows_the_channel_map() {
+        assert_eq!(mono_fill(6, Some(&[2])), [0.0, 0.0, 1.0, 0.0, 0.0, 0.0]);
+        assert_eq!(mono_fill(6, Some(&[0, 4, 5])), [1.0, 0.0, 0.0, 0.0, 1.0, 1.0]);
+        assert!(validate_output_channels(6, Some(&[6]), 1).is_err());
+        assert!(validate_output_channels(6, Some(&[]), 1).is_err());
+    }
+
+    #[test]
+    fn mono_output_device_gets_the_signal() {
+        assert_eq!(mono_fill(1, None), [1.0]);
+        assert!(validate_output_channels(1, None, 1).is_ok());
+        // A stereo session is folded down onto the single channel.
+        let routing = output_routing(1, None, 2);
+        assert_eq!(routing.iter().map(|r| r.sample(&[0.2, 0.6])).collect::<Vec<_>>(), [0.4]);
+        assert!(validate_output_channels(0, None, 1).is_err());
+    }
+
+    #[test]
+    fn stereo_tones_round_trip_without_swapping_channels() {
+        // Half a second of 500Hz left, 1500Hz right from a 44.1kHz mic.
+        let sine = |hz: f64, i: usize| (2.0 * std::f64::consts::PI * hz * i as f64 / 44100.0).sin() as f32 * 0.5;
+        let (mut mic, cons) = HeapRb::<f32>::new(44100 * 2).split();
+        for i in 0..22050 {
+            mic.push(sine(500.0, i)).unwrap();
+            mic.push(sine(1500.0, i)).unwrap();
+        }
+
+        let settings = AudioSettings { dsp_profile: DspProfile::None, channels: 2, ..AudioSettings::default() };
+        let mut capture = CaptureResampler::new(cons, 44100.0, ResamplerQuality::High, 2);
+        let mut chain = DspChain::new(&settings);
+        let (prod, mut speaker) = HeapRb::<f32>::new(48000 * 2).split();
+        let mut playback = PlaybackResampler::new(prod, 48000.0, ResamplerQuality::High, 2);
+        for _ in 0..1000 {
+            capture.poll();
+            while let Some(frame) = capture.next_frame() {
+                playback.push_frame(chain.process_frame(frame, true));
+            }
+        }
+
+        // What a stereo speaker's two channels play.
+        let routing = output_routing(2, None, 2);
+        let (mut left, mut right) = (Vec::new(), Vec::new());
+        while speaker.len() >= 2 {
+            let frame = [speaker.pop().unwrap(), speaker.pop().unwrap()];
+            left.push(routing[0].sample(&frame));
+            right.push(routing[1].sample(&frame));
+        }
+        assert!((tone_hz(&left) - 500.0).abs() < 10.0, "left {}Hz", tone_hz(&left));
+        assert!((tone_hz(&right) - 1500.0).abs() < 10.0, "right {}Hz", tone_hz(&right));
+    }
+
+    // --- PTT KEYS ---
+
+    #[test]
+    fn modifier_family_matches_both_sides_when_enabled() {
+        assert!(ptt_key_matches(Key::ControlLeft, Key::ControlLeft, true));
+        assert!(ptt_key_matches(Key::ControlLeft, Key::ControlRight, true));
+        assert!(ptt_key_matches(Key::ControlRight, Key::ControlLeft, true));
+        assert!(!ptt_key_matches(Key::ControlLeft, Key::ShiftRight, true));
+    }
+
+    #[test]
+    fn modifier_sides_are_distinct_by_default() {
+        assert!(ptt_key_matches(Key::ControlLeft, Key::ControlLeft, false));
+        assert!(!ptt_key_matches(Key::ControlLeft, Key::ControlRight, false));
+    }
+
+    #[test]
+    fn non_modifiers_only_match_themselves() {
+        assert!(ptt_key_matches(Key::KeyV, Key::KeyV, true));
+        assert!(!ptt_key_matches(Key::KeyV, Key::KeyB, true));
+    }
+
+    // --- IDLE TIMEOUT ---
+
+    /// The frame index (from 1) on which `tracker` first reports the timeout.
+    fn fires_after(tracker: &mut IdleTracker, frames: &[Vec<f32>]) -> Option<usize> {
+        frames.iter().position(|f| tracker.update(f)).map(|i| i + 1)
+    }
+
+    #[test]
+    fn silence_past_the_threshold_fires_once() {
+        let mut tracker = IdleTracker::new(Duration::from_millis(500));
+        let silence = vec![vec![0.0f32; FRAME_SIZE]; 200];
+        assert_eq!(fires_after(&mut tracker, &silence), Some(50));
+        assert_eq!(fires_after(&mut tracker, &silence), None);
+    }
+
+    #[test]
+    fn audio_resets_the_timer() {
+        let mut tracker = IdleTracker::new(Duration::from_millis(500));
+        let mut frames = vec![vec![0.0f32; FRAME_SIZE]; 40];
+        frames.push(vec![0.1; FRAME_SIZE]);
+        frames.extend(vec![vec![0.0f32; FRAME_SIZE]; 100]);
+        assert_eq!(fires_after(&mut tracker, &frames), Some(41 + 50));
+    }
+
+    #[test]
+    fn sub_frame_timeouts_round_up_to_one_frame() {
+        let silence = vec![vec![0.0f32; FRAME_SIZE]; 3];
+        assert_eq!(fires_after(&mut IdleTracker::new(Duration::from_millis(3)), &silence), Some(1));
+        assert_eq!(fires_after(&mut IdleTracker::new(Duration::ZERO), &silence), Some(1));
+        assert_eq!(fires_after(&mut IdleTracker::new(Duration::from_millis(15)), &silence), Some(2));
+    }
+
+    /// Feeds `(press, at_ms)` events to a `ToggleKey` and returns the transmit
+    /// state after each, starting from muted.
+    fn toggle_states(events: &[(bool, u64)]) -> Vec<bool> {
+        let mut key = ToggleKey::default();
+        let mut transmitting = false;
+        events
+            .iter()
+            .map(|&(press, ms)| {
+                let at = SystemTime::UNIX_EPOCH + Duration::from_millis(ms);
+                if press {
+                    transmitting ^= key.press(at);
+                } else {
+                    key.release(at);
+                }
+                transmitting
+            })
+            .collect()
+    }
+
+    #[test]
+    fn each_fresh_toggle_press_flips_the_state() {
+        let states = toggle_states(&[(true, 0), (false, 100), (true, 500), (false, 600), (true, 1000)]);
+        assert_eq!(states, [true, true, false, false, true]);
+    }
+
+    #[test]
+    fn auto_repeat_while_held_does_not_toggle() {
+        // Repeated presses without a release.
+        assert_eq!(toggle_states(&[(true, 0), (true, 500), (true, 530), (false, 900)]), [true; 4]);
+        // Repeats sent as release/press pairs with near-identical timestamps.
+        let states = toggle_states(&[(true, 0), (false, 500), (true, 500), (false, 530), (true, 545), (false, 900), (true, 1400)]);
+        assert_eq!(states, [true, true, true, true, true, true, false]);
+    }
+
+    #[test]
+    fn second_key_capture_is_refused_while_one_waits() {
+        let state = Arc::new(GlobalAudioState::new(false));
+        assert!(!state.deliver_captured_key(Key::KeyA));
+
+        let waiting = state.clone();
+        let first = std::thread::spawn(move || waiting.capture_next_key(Duration::from_secs(5)));
+        while state.key_capture.lock().is_none() {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        let started = Instant::now();
+        assert_eq!(state.capture_next_key(Duration::from_secs(5)), None);
+        assert!(started.elapsed() < Duration::from_secs(1));
+
+        // The refused call left the first one's slot alone.
+        assert!(state.deliver_captured_key(Key::KeyB));
+        assert_eq!(first.join().unwrap(), Some(Key::KeyB));
+        assert!(state.key_capture.lock().is_none());
+    }
+
+    // --- DRIFT COMPENSATION ---
+
+    /// Plays 2 minutes into a speaker whose clock runs 0.2% slow, starting with
+    /// 100ms queued after a hiccup. Returns the average fill of the last 10s in ms.
+    fn slow_speaker_fill_ms(target_fill_ms: Option<u16>) -> f64 {
+        let (prod, mut cons) = HeapRb::<f32>::new(48000 * 2).split();
+        let mut playback = PlaybackResampler::new(prod, 48000.0, ResamplerQuality::Fast, 1).with_target_fill(target_fill_ms);
+        playback.push_frame(vec![0.0; 4800]);
+        let mut owed = 0.0;
+        let mut fill = 0;
+        for i in 0..12000 {
+            playback.push_frame(vec![0.0; FRAME_SIZE]);
+            if i >= 11000 {
+                fill += cons.len();
+            }
+            owed += FRAME_SIZE as f64 * 0.998;
+            for _ in 0..owed as usize {
+                cons.pop();
+            }
+            owed = owed.fract();
+        }
+        fill as f64 / 1000.0 / 48.0
+    }
+
+    #[test]
+    fn slow_consumer_converges_to_target_fill() {
+        let fill = slow_speaker_fill_ms(Some(20));
+        // Proportional control leaves a small offset: 0.2% drift needs 40% of the correction range.
+        assert!((20.0..30.0).contains(&fill), "{}ms", fill);
+        // Without compensation the queue only grows.
+        assert!(slow_speaker_fill_ms(None) > 300.0);
+    }
+
+    #[test]
+    fn playback_at_44k1_is_continuous() {
+        let (prod, mut cons) = HeapRb::<f32>::new(48000 * 2).split();
+        let mut playback = PlaybackResampler::new(prod, 44100.0, ResamplerQuality::High, 1);
+        let tone: Vec<f32> = (0..48000).map(|i| (2.0 * std::f64::consts::PI * 1000.0 * i as f64 / 48000.0).sin() as f32 * 0.5).collect();
+        for frame in tone.chunks(FRAME_SIZE) {
+            playback.push_frame(frame.to_vec());
+        }
+        let out: Vec<f32> = std::iter::from_fn(|| cons.pop()).collect();
+        // 100 frames of 480 at 48k is one second at 44.1k, less the sinc filter's delay.
+        assert!((43000..=44100).contains(&out.len()), "{} samples", out.len());
+        // A 1kHz tone at half scale moves at most 0.5 * 2pi * 1000 / 44100 per sample;
+        // a gap or a repeated chunk would jump further.
+        let max_step = out[1000..].windows(2).map(|w| (w[1] - w[0]).abs()).fold(0.0f32, f32::max);
+        assert!(max_step < 0.075, "step {}", max_step);
+    }
+
+    // --- TRANSMIT EVENTS ---
+
+    fn after_debounce(state: &GlobalAudioState) {
+        std::thread::sleep(TRANSMIT_DEBOUNCE + Duration::from_millis(10));
+        state.flush_transmit_events();
+    }
+
+    #[test]
+    fn transmit_change_is_reported_once_it_holds() {
+        let state = GlobalAudioState::new(false);
+        let events = state.subscribe_transmit_events();
+        let before = Instant::now();
+        state.set_transmitting(true);
+        state.flush_transmit_events();
+        assert!(events.try_recv().is_err(), "reported before the debounce window passed");
+
+        after_debounce(&state);
+        let event = events.try_recv().unwrap();
+        assert!(event.transmitting);
+        assert!(event.at >= before && event.at.elapsed() >= TRANSMIT_DEBOUNCE);
+
+        state.toggle_transmitting();
+        after_debounce(&state);
+        assert!(!events.try_recv().unwrap().transmitting);
+    }
+
+    #[test]
+    fn ptt_chatter_is_coalesced() {
+        let state = GlobalAudioState::new(false);
+        let events = state.subscribe_transmit_events();
+        for _ in 0..5 {
+            state.set_transmitting(true);
+            state.set_transmitting(false);
+        }
+        state.set_transmitting(true);
+        after_debounce(&state);
+        assert!(events.try_recv().unwrap().transmitting);
+        assert!(events.try_recv().is_err());
+    }
+
+    #[test]
+    fn unchanged_state_sends_nothing() {
+        let state = GlobalAudioState::new(true);
+        let events = state.subscribe_transmit_events();
+        state.set_transmitting(true);
+        after_debounce(&state);
+        assert!(events.try_recv().is_err());
+    }
+
+    // --- DSP CHAIN ---
+
+    #[test]
+    fn transient_suppressor_setting_reaches_the_config() {
+        let on = AudioSettings { transient_suppressor_enabled: true, ..AudioSettings::default() };
+        let off = AudioSettings { transient_suppressor_enabled: false, ..AudioSettings::default() };
+        assert!(DspChain::processor_config(&on).enable_transient_suppressor);
+        assert!(!DspChain::processor_config(&off).enable_transient_suppressor);
+    }
+
+    #[test]
+    fn offline_processing_reduces_noise_energy() {
+        // Three seconds of white noise from a fixed LCG.
+        let mut seed = 1u32;
+        let noise: Vec<f32> = (0..48000 * 3)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                (seed >> 8) as f32 / (1 << 24) as f32 * 0.2 - 0.1
+            })
+            .collect();
+        let settings = AudioSettings { agc_enabled: false, ..AudioSettings::default() };
+        let out = process_audio_frames(&noise, &settings);
+        assert_eq!(out.len(), noise.len());
+
+        // Past the first second the suppressors have adapted.
+        let energy = |s: &[f32]| s[48000..].iter().map(|s| s * s).sum::<f32>();
+        let reduction_db = 10.0 * (energy(&out) / energy(&noise)).log10();
+        assert!(reduction_db < -10.0, "{:.1}dB", reduction_db);
+    }
+
+    #[test]
+    fn audio_flows_through_the_software_fallback() {
+        let settings = AudioSettings { agc_enabled: true, ..AudioSettings::default() };
+        let mut chain = DspChain::with_processor(&settings, |_| Err("webrtc init failed"));
+        assert!(chain.proc.is_none());
+        assert!(chain.fallback_agc.is_some());
+
+        // Two seconds of a quiet 300Hz tone with PTT held.
+        let tone: Vec<f32> = (0..96000).map(|i| (2.0 * std::f32::consts::PI * 300.0 * i as f32 / 48000.0).sin() * 0.05).collect();
+        let out: Vec<f32> = tone.chunks(FRAME_SIZE).flat_map(|f| chain.process_frame(f.to_vec(), true)).collect();
+        let peak = out[48000..].iter().fold(0.0f32, |m, s| m.max(s.abs()));
+        assert!(peak > 0.05, "peak {}", peak);
+        assert!(out.iter().all(|s| s.is_finite() && s.abs() <= 1.0));
+    }
+
+    #[test]
+    fn low_suppression_without_the_extra_pass_reaches_the_config() {
+        let settings =
+            AudioSettings { noise_suppression_level: NoiseSuppressionLevel::Low, enable_extra_denoise: false, ..AudioSettings::default() };
+        let ns = DspChain::processor_config(&settings).noise_suppression.expect("stock denoiser uses webrtc NS");
+        assert!(matches!(ns.suppression_level, NoiseSuppressionLevel::Low));
+        // A custom denoiser replaces webrtc NS.
+        let custom = AudioSettings { denoiser: Some(Arc::new(|| Box::new(Passthrough) as Box<dyn Denoiser>)), ..settings };
+        assert!(DspChain::processor_config(&custom).noise_suppression.is_none());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn exclusive_access_opens_the_hw_device() {
+        assert_eq!(input_device_id("plughw:CARD=PCH,DEV=0", DeviceExclusivity::Shared).unwrap(), "plughw:CARD=PCH,DEV=0");
+        assert_eq!(input_device_id("plughw:CARD=USB,DEV=1", DeviceExclusivity::Exclusive).unwrap(), "hw:CARD=USB,DEV=1");
+        assert_eq!(input_device_id("sysdefault:CARD=PCH", DeviceExclusivity::Exclusive).unwrap(), "hw:CARD=PCH,DEV=0");
+        assert!(matches!(input_device_id("default", DeviceExclusivity::Exclusive), Err(AudioError::Unsupported(_))));
+    }
+
+    // --- DEFAULT DEVICE TRACKING ---
+
+    fn watcher(input: &str, output: &str) -> DefaultDeviceWatcher {
+        DefaultDeviceWatcher { input: Some(input.to_string()), output: Some(output.to_string()), last_check: Instant::now() }
+    }
+
+    #[test]
+    fn new_default_input_reopens_default_sessions_only() {
+        let mut w = watcher("Built-in Microphone", "Speakers");
+        let changed = w.update(Some("USB Headset".to_string()), Some("Speakers".to_string()));
+        assert_eq!(changed, (true, false));
+        assert!(default_change_needs_reopen("default", true, changed));
+        assert!(!default_change_needs_reopen("plughw:CARD=PCH,DEV=0", true, changed));
+        // A LiveKit session still follows the input default.
+        assert!(default_change_needs_reopen("default", false, changed));
+    }
+
+    #[test]
+    fn new_default_output_reopens_every_local_session() {
+        let mut w = watcher("Built-in Microphone", "Speakers");
+        let changed = w.update(Some("Built-in Microphone".to_string()), Some("USB Headset".to_string()));
+        assert_eq!(changed, (false, true));
+        assert!(default_change_needs_reopen("plughw:CARD=PCH,DEV=0", true, changed));
+        // A LiveKit session publishes to the room and has no local output to follow.
+        assert!(!default_change_needs_reopen("default", false, changed));
+        assert!(!default_change_needs_reopen("plughw:CARD=PCH,DEV=0", false, changed));
+    }
+
+    #[test]
+    fn unchanged_or_lost_defaults() {
+        let mut w = watcher("Built-in Microphone", "Speakers");
+        let same = w.update(Some("Built-in Microphone".to_string()), Some("Speakers".to_string()));
+        assert!(!default_change_needs_reopen("default", true, same));
+        // An unplugged default (no device reported) is a change too.
+        assert_eq!(w.update(None, Some("Speakers".to_string())), (true, false));
+    }
+
+    #[test]
+    fn none_profile_is_bit_exact_behind_the_gate() {
+        // A configured EQ is processing too, so it is skipped as well.
+        let eq = EqSettings { mid_gain_db: 6.0, ..EqSettings::default() };
+        let settings = AudioSettings { dsp_profile: DspProfile::None, channels: 2, input_eq: Some(eq), ..AudioSettings::default() };
+        let mut chain = DspChain::new(&settings);
+        let input: Vec<f32> = (0..FRAME_SIZE * 2 * 10).map(|i| ((i * 7919) % 2001) as f32 / 1000.0 - 1.0).collect();
+        let out: Vec<f32> = input.chunks(FRAME_SIZE * 2).flat_map(|f| chain.process_frame(f.to_vec(), true)).collect();
+        // Only the gate's fade-in touches the first frame.
+        let ramp = GATE_RAMP_SAMPLES * 2;
+        assert_eq!(&out[ramp..], &input[ramp..]);
+
+        // Released, the gate closes and nothing gets through.
+        let closed = chain.process_frame(input[..FRAME_SIZE * 2].to_vec(), false);
+        assert!(closed.iter().all(|&s| s == 0.0));
+    }
+
+    /// Records the first sample of every frame it is given.
+    struct Recorder(Arc<Mutex<Vec<f32>>>);
+
+    impl Denoiser for Recorder {
+        fn process(&mut self, frame: &mut [f32; FRAME_SIZE]) {
+            self.0.lock().push(frame[0]);
+        }
+    }
+
+    #[test]
+    fn gated_chain_skips_the_denoiser_and_primes_it_on_resume() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let recorder = seen.clone();
+        let settings = AudioSettings {
+            agc_enabled: false,
+            denoiser: Some(Arc::new(move || Box::new(Recorder(recorder.clone())) as Box<dyn Denoiser>)),
+            ..AudioSettings::default()
+        };
+        // No webrtc processor, so the denoiser sees each frame untouched.
+        let mut chain = DspChain::with_processor(&settings, |_| Err("webrtc disabled"));
+        // Frame `i` is filled with `i`; PTT is up throughout.
+        for i in 1..=60 {
+            chain.process_frame(vec![i as f32; FRAME_SIZE], false);
+        }
+        let expected: Vec<f32> = (1..=IDLE_AFTER_FRAMES).map(|i| i as f32).collect();
+        assert_eq!(*seen.lock(), expected);
+
+        // The press replays the last idle frames before the new one.
+        chain.process_frame(vec![61.0; FRAME_SIZE], true);
+        let primed: Vec<f32> = (61 - PRIME_FRAMES..=61).map(|i| i as f32).collect();
+        assert_eq!(seen.lock()[IDLE_AFTER_FRAMES..], primed[..]);
+    }
+
+    /// CPU of each profile while transmitting vs gated idle; run with
+    /// `cargo test --release -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_profiles_and_idle() {
+        let mut seed = 1u32;
+        let frame: Vec<f32> = (0..FRAME_SIZE)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                (seed >> 8) as f32 / (1 << 24) as f32 * 0.2 - 0.1
+            })
+            .collect();
+        for dsp_profile in [DspProfile::Full, DspProfile::RnnoiseOnly, DspProfile::None] {
+            let mut times = Vec::new();
+            for is_tx in [true, false] {
+                let mut chain = DspChain::new(&AudioSettings { dsp_profile, ..AudioSettings::default() });
+                let start = Instant::now();
+                // 60s of audio.
+                for _ in 0..6000 {
+                    std::hint::black_box(chain.process_frame(frame.clone(), is_tx));
+                }
+                let elapsed = start.elapsed();
+                println!("{:?} {}: {:?} per minute of audio", dsp_profile, if is_tx { "transmitting" } else { "gated" }, elapsed);
+                times.push(elapsed);
+            }
+            println!("{:?}: idle saves {:.0}%", dsp_profile, 100.0 * (1.0 - times[1].as_secs_f64() / times[0].as_secs_f64()));
+        }
+    }
+
+    // --- TRANSMIT PREBUFFER ---
+
+    /// Runs 1s of silence with PTT up, then `word` with PTT pressed on its first
+    /// frame, and returns the output from the press on.
+    fn speak_at_press(word: &[f32], prebuffer_ms: u16) -> Vec<f32> {
+        let settings = AudioSettings { dsp_profile: DspProfile::None, transmit_prebuffer_ms: prebuffer_ms, ..AudioSettings::default() };
+        let mut chain = DspChain::new(&settings);
+        for _ in 0..100 {
+            chain.process_frame(vec![0.0; FRAME_SIZE], false);
+        }
+        let tail = vec![0.0; FRAME_SIZE * MAX_PREBUFFER_FRAMES];
+        word.chunks(FRAME_SIZE).chain(tail.chunks(FRAME_SIZE)).flat_map(|f| chain.process_frame(f.to_vec(), true)).collect()
+    }
+
+    fn word() -> Vec<f32> {
+        (0..FRAME_SIZE * 20).map(|i| 0.5 * (i as f32 * 0.05).sin()).collect()
+    }
+
+    #[test]
+    fn word_starting_at_ptt_press_is_fully_captured() {
+        let word = word();
+        let out = speak_at_press(&word, 50);
+        let delay = 5 * FRAME_SIZE;
+        assert!(out[..delay].iter().all(|&s| s == 0.0));
+        assert_eq!(&out[delay..delay + word.len()], &word[..]);
+    }
+
+    #[test]
+    fn without_prebuffer_the_gate_fade_in_hits_the_word() {
+        let word = word();
+        let out = speak_at_press(&word, 0);
+        assert_ne!(&out[..GATE_RAMP_SAMPLES], &word[..GATE_RAMP_SAMPLES]);
+        assert_eq!(&out[GATE_RAMP_SAMPLES..word.len()], &word[GATE_RAMP_SAMPLES..]);
+    }
+
+    #[test]
+    fn lowering_the_prebuffer_on_a_running_chain_cuts_the_delay_at_once() {
+        // PTT disabled: the gate never closes, so the chain is never re-primed.
+        let settings = AudioSettings { dsp_profile: DspProfile::None, transmit_prebuffer_ms: 100, ..AudioSettings::default() };
+        let mut chain = DspChain::new(&settings);
+        // Each frame is filled with its own index, so an output frame tells which input it was.
+        let push = |chain: &mut DspChain, i: usize| chain.process_frame(vec![i as f32; FRAME_SIZE], true)[FRAME_SIZE - 1];
+        for i in 1..=30 {
+            assert_eq!(push(&mut chain, i), i.saturating_sub(10) as f32);
+        }
+
+        chain.update(&AudioSettings { transmit_prebuffer_ms: 20, ..settings });
+        for i in 31..=40 {
+            assert_eq!(push(&mut chain, i), (i - 2) as f32);
+        }
+    }
+
+    // --- SAMPLE CONVERSION ---
+
+    #[test]
+    fn i32_samples_use_the_full_32_bit_container() {
+        // 24-bit full scale as cpal delivers it, left-justified in the i32.
+        let max_24 = 0x7f_ffff << 8;
+        let min_24 = -0x80_0000 << 8;
+        assert_eq!(i32_to_f32(min_24), -1.0);
+        assert!((i32_to_f32(max_24) - 1.0).abs() < 1e-6);
+        // Same scale as true 32-bit samples, so neither clips or is attenuated.
+        assert_eq!(i32_to_f32(i32::MIN), i32_to_f32(min_24));
+        assert!((i32_to_f32(i32::MAX) - i32_to_f32(max_24)).abs() < 1e-6);
+        // The smallest 24-bit step stays resolvable.
+        assert_eq!(i32_to_f32(1 << 8), 1.0 / 8_388_608.0);
+        assert_eq!(i32_to_f32(0), 0.0);
+    }
+
+    #[test]
+    fn f32_to_i16_clamps_at_full_scale() {
+        assert_eq!(f32_to_i16(0.0), 0);
+        assert_eq!(f32_to_i16(1.0), i16::MAX);
+        assert_eq!(f32_to_i16(-1.0), -i16::MAX);
+        assert_eq!(f32_to_i16(1.5), i16::MAX);
+        assert_eq!(f32_to_i16(-7.0), -i16::MAX);
+        assert_eq!(f32_to_i16(0.5), i16::MAX / 2);
+    }
+}
@@ -0,0 +1,318 @@
+use cpal::traits::StreamTrait;
+use parking_lot::Mutex;
+use ringbuf::HeapRb;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+#[cfg(target_os = "windows")]
+use std::{collections::VecDeque, sync::mpsc};
+
+#[cfg(target_os = "windows")]
+use crate::loopback::{SystemAudioLoopback, SYSTEM_AUDIO_INPUT_ID};
+
+use crate::{
+    build_capture_stream, build_playback_stream, check_settings, exclusive_hint, open_input_device, resolve_output_device,
+    AudioSettings, CaptureResampler, DspChain, GlobalAudioState, LoudnessNormalizer, OverflowMonitor, PlaybackResampler, FRAME_SIZE,
+};
+
+/// Upper bound on 48kHz samples buffered per input. Devices run on independent
+/// clocks, so a slightly faster one would otherwise accumulate latency forever.
+const MAX_INPUT_BACKLOG: usize = FRAME_SIZE * 10;
+/// Backlog on some input after which inputs without a whole frame are mixed in
+/// as silence, so one stalled device doesn't hold up the others.
+const STALL_BACKLOG: usize = FRAME_SIZE * 3;
+
+#[derive(Clone, Copy)]
+pub struct InputMix {
+    pub gain: f32,
+    pub muted: bool,
+}
+
+impl Default for InputMix {
+    fn default() -> Self {
+        Self { gain: 1.0, muted: false }
+    }
+}
+
+/// Sums one frame from each input, applying that input's gain and mute.
+pub fn mix_frames(frames: &[Vec<f32>], mix: &[InputMix]) -> Vec<f32> {
+    let mut out = vec![0.0f32; FRAME_SIZE];
+    for (frame, m) in frames.iter().zip(mix) {
+        if m.muted {
+            continue;
+        }
+        for (o, &s) in out.iter_mut().zip(frame) {
+            *o += s * m.gain;
+        }
+    }
+    out
+}
+
+/// The next frame of every input, or `None` to wait for more input. While
+/// only some inputs have a whole frame the rest get a moment to catch up; once
+/// an input is `STALL_BACKLOG` ahead the missing ones are mixed as silence.
+fn next_frames(inputs: &mut [MixerInput]) -> Option<Vec<Vec<f32>>> {
+    let ready = inputs.iter().filter(|i| i.buffered() >= FRAME_SIZE).count();
+    let backlog = inputs.iter().map(MixerInput::buffered).max().unwrap_or(0);
+    if ready == 0 || (ready < inputs.len() && backlog < STALL_BACKLOG) {
+        return None;
+    }
+    Some(inputs.iter_mut().map(|i| i.next_frame().unwrap_or_else(|| vec![0.0; FRAME_SIZE])).collect())
+}
+
+/// One mixer input, yielding 48kHz mono frames.
+enum MixerInput {
+    Device { capture: Box<CaptureResampler>, overflow: OverflowMonitor },
+    /// Desktop audio from `SystemAudioLoopback`, which already delivers whole frames.
+    #[cfg(target_os = "windows")]
+    SystemAudio { frames: mpsc::Receiver<Vec<f32>>, queued: VecDeque<Vec<f32>>, connected: bool, overflow: OverflowMonitor },
+}
+
+impl MixerInput {
+    fn poll(&mut self) {
+        match self {
+            MixerInput::Device { capture, overflow } => {
+                overflow.poll();
+                capture.poll();
+            }
+            #[cfg(target_os = "windows")]
+            MixerInput::SystemAudio { frames, queued, connected, overflow } => {
+                overflow.poll();
+                loop {
+                    match frames.try_recv() {
+                        Ok(frame) => queued.push_back(frame),
+                        Err(mpsc::TryRecvError::Empty) => break,
+                        Err(mpsc::TryRecvError::Disconnected) => {
+                            if *connected {
+                                println!("⚠️ System audio loopback stopped");
+                                *connected = false;
+                            }
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// 48kHz samples ready to mix.
+    fn buffered(&self) -> usize {
+        match self {
+            MixerInput::Device { capture, .. } => capture.buffered(),
+            #[cfg(target_os = "windows")]
+            MixerInput::SystemAudio { queued, .. } => queued.len() * FRAME_SIZE,
+        }
+    }
+
+    fn next_frame(&mut self) -> Option<Vec<f32>> {
+        match self {
+            MixerInput::Device { capture, .. } => capture.next_frame(),
+            #[cfg(target_os = "windows")]
+            MixerInput::SystemAudio { queued, .. } => queued.pop_front(),
+        }
+    }
+
+    fn trim_backlog(&mut self, max: usize) {
+        match self {
+            MixerInput::Device { capture, .. } => capture.trim_backlog(max),
+            #[cfg(target_os = "windows")]
+            MixerInput::SystemAudio { queued, .. } => {
+                while queued.len() * FRAME_SIZE > max {
+                    queued.pop_front();
+                }
+            }
+        }
+    }
+}
+
+/// Like `AudioSession`, but captures several input devices (e.g. mic + desktop
+/// audio) and mixes them into one stream before the shared DSP chain and PTT gate.
+pub struct AudioMixerSession {
+    _streams: Vec<cpal::Stream>,
+    #[cfg(target_os = "windows")]
+    _loopback: Option<SystemAudioLoopback>,
+    mix: Arc<Mutex<Vec<InputMix>>>,
+    /// Cleared to make the mixing thread exit.
+    running: Arc<AtomicBool>,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+impl AudioMixerSession {
+    /// Mixes in mono, so `settings.channels` must be 1. On Windows the id
+    /// `SYSTEM_AUDIO_INPUT_ID` mixes in desktop audio via WASAPI loopback.
+    pub fn create(in_ids: &[&str], state: Arc<GlobalAudioState>, settings: AudioSettings) -> anyhow::Result<Self> {
+        if in_ids.is_empty() {
+            return Err(anyhow::anyhow!("Mixer needs at least one input"));
+        }
+        if settings.channels != 1 {
+            return Err(anyhow::anyhow!("Mixer is mono only, got channels = {}", settings.channels));
+        }
+        check_settings(&settings)?;
+
+        let host = cpal::default_host();
+        let out_device = resolve_output_device(&host, "default")?;
+
+        let mut streams = Vec::new();
+        let mut inputs = Vec::new();
+        #[cfg(target_os = "windows")]
+        let mut loopback = None;
+        for id in in_ids {
+            #[cfg(target_os = "windows")]
+            if *id == SYSTEM_AUDIO_INPUT_ID {
+                if loopback.is_some() {
+                    return Err(anyhow::anyhow!("System audio can only be mixed in once"));
+                }
+                let (lb, frames) = SystemAudioLoopback::start()?;
+                let overflow = OverflowMonitor::new("System audio loopback", lb.dropped(), 48000.0, 1);
+                loopback = Some(lb);
+                inputs.push(MixerInput::SystemAudio { frames, queued: VecDeque::new(), connected: true, overflow });
+                continue;
+            }
+            let device = open_input_device(&host, id, settings.exclusivity)?;
+            let dropped = Arc::new(AtomicU64::new(0));
+            let (stream, sr, cons) =
+                build_capture_stream(&device, 1, dropped.clone(), |e| println!("⚠️ Mixer input stream error: {}", e))
+                    .map_err(|e| exclusive_hint(e, settings.exclusivity))?;
+            streams.push(stream);
+            inputs.push(MixerInput::Device {
+                capture: Box::new(CaptureResampler::new(cons, sr, settings.resampler_quality, 1)),
+                overflow: OverflowMonitor::new(format!("Mixer input '{}'", id), dropped, sr, 1),
+            });
+        }
+
+        let (prod_out, cons_out) = HeapRb::<f32>::new(48000 * 2).split();
+        let (out_stream, out_sr) = build_playback_stream(&out_device, cons_out, settings.output_channel_map.as_deref(), 1, |e| {
+            println!("⚠️ Mixer output stream error: {}", e)
+        })?;
+        streams.push(out_stream);
+
+        let mix = Arc::new(Mutex::new(vec![InputMix::default(); in_ids.len()]));
+        let thread_mix = mix.clone();
+        let running = Arc::new(AtomicBool::new(true));
+        let thread_running = running.clone();
+
+        // Start the streams first so a failure returns before there is a thread to stop.
+        for s in &streams {
+            s.play()?;
+        }
+        let thread = std::thread::spawn(move || {
+            let mut chain = DspChain::new(&settings);
+            let mut playback = PlaybackResampler::new(prod_out, out_sr, settings.resampler_quality, 1)
+                .with_target_fill(settings.output_target_fill_ms);
+            let mut normalizer = settings.output_loudness_target_lufs.map(|t| LoudnessNormalizer::new(t, 1));
+
+            while thread_running.load(Ordering::Relaxed) {
+                for input in inputs.iter_mut() {
+                    input.poll();
+                }
+                state.flush_transmit_events();
+
+                while let Some(frames) = next_frames(&mut inputs) {
+                    let mixed = mix_frames(&frames, &thread_mix.lock());
+                    let is_tx = state.is_transmitting.load(Ordering::Relaxed);
+                    let mut out = chain.process_frame(mixed, is_tx);
+                    if let Some(n) = normalizer.as_mut() {
+                        n.process(&mut out);
+                    }
+                    playback.push_frame(out);
+                }
+
+                for input in inputs.iter_mut() {
+                    input.trim_backlog(MAX_INPUT_BACKLOG);
+                }
+                std::thread::sleep(Duration::from_millis(1));
+            }
+        });
+
+        Ok(Self {
+            _streams: streams,
+            #[cfg(target_os = "windows")]
+            _loopback: loopback,
+            mix,
+            running,
+            thread: Some(thread),
+        })
+    }
+
+    /// Sets the linear gain applied to input `index` before mixing.
+    pub fn set_input_gain(&self, index: usize, gain: f32) {
+        if let Some(m) = self.mix.lock().get_mut(index) {
+            m.gain = gain.max(0.0);
+        }
+    }
+
+    pub fn set_input_muted(&self, index: usize, muted: bool) {
+        if let Some(m) = self.mix.lock().get_mut(index) {
+            m.muted = muted;
+        }
+    }
+}
+
+impl Drop for AudioMixerSession {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(handle) = self.thread.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ResamplerQuality;
+    use ringbuf::HeapProducer;
+
+    fn tone(hz: f32) -> Vec<f32> {
+        (0..FRAME_SIZE).map(|i| (2.0 * std::f32::consts::PI * hz * i as f32 / 48000.0).sin()).collect()
+    }
+
+    #[test]
+    fn two_tones_sum_with_their_gains() {
+        let (low, high) = (tone(440.0), tone(1000.0));
+        let mix = [InputMix { gain: 0.5, muted: false }, InputMix { gain: 0.25, muted: false }];
+        let out = mix_frames(&[low.clone(), high.clone()], &mix);
+        for ((o, l), h) in out.iter().zip(&low).zip(&high) {
+            assert!((o - (0.5 * l + 0.25 * h)).abs() < 1e-6);
+        }
+
+        let muted = [mix[0], InputMix { muted: true, ..mix[1] }];
+        let out = mix_frames(&[low.clone(), high], &muted);
+        assert!(out.iter().zip(&low).all(|(o, l)| (o - 0.5 * l).abs() < 1e-6));
+    }
+
+    /// A 48kHz device input and the producer that plays its "device".
+    fn device_input() -> (MixerInput, HeapProducer<f32>) {
+        let (prod, cons) = HeapRb::<f32>::new(48000).split();
+        let capture = CaptureResampler::new(cons, 48000.0, ResamplerQuality::Fast, 1);
+        let overflow = OverflowMonitor::new("test", Arc::new(AtomicU64::new(0)), 48000.0, 1);
+        (MixerInput::Device { capture: Box::new(capture), overflow }, prod)
+    }
+
+    /// Polls until everything the devices produced has been resampled.
+    fn poll(inputs: &mut [MixerInput]) {
+        for _ in 0..20 {
+            inputs.iter_mut().for_each(MixerInput::poll);
+        }
+    }
+
+    #[test]
+    fn stalled_input_is_mixed_as_silence_once_others_back_up() {
+        let (mic, mut mic_dev) = device_input();
+        let (desktop, _stalled) = device_input();
+        let mut inputs = [mic, desktop];
+
+        // A frame short of the stall backlog from the mic: the stalled input gets time to catch up.
+        mic_dev.push_slice(&[0.5; STALL_BACKLOG - FRAME_SIZE]);
+        poll(&mut inputs);
+        assert!(next_frames(&mut inputs).is_none());
+
+        // Past the stall backlog the mic goes on alone.
+        mic_dev.push_slice(&[0.5; STALL_BACKLOG]);
+        poll(&mut inputs);
+        let frames = next_frames(&mut inputs).expect("mic frame");
+        assert!(frames[0].iter().skip(1).all(|&s| (s - 0.5).abs() < 1e-6));
+        assert!(frames[1].iter().all(|&s| s == 0.0));
+        assert_eq!(frames[1].len(), FRAME_SIZE);
+    }
+}
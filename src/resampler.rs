@@ -0,0 +1,192 @@
+use rubato::{Resampler, SincFixedIn, SincInterpolationParameters, SincInterpolationType, WindowFunction};
+
+use crate::FRAME_SIZE;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResamplerQuality {
+    /// Band-limited sinc interpolation (rubato).
+    High,
+    /// Plain linear interpolation. Much cheaper, fine for voice on small
+    /// ratio changes like 44.1k -> 48k. It has no anti-alias filter, so
+    /// downsampling by more than `LINEAR_MIN_RATIO` uses the sinc path instead.
+    Fast,
+}
+
+/// Smallest output/input ratio the linear path is used for. Below it (e.g. 96k -> 48k)
+/// content above the new Nyquist would fold back audibly without a low-pass.
+pub const LINEAR_MIN_RATIO: f64 = 0.9;
+
+/// Streaming linear-interpolation resampler that consumes fixed-size chunks.
+/// It does not band-limit; see `LINEAR_MIN_RATIO`.
+pub struct LinearResampler {
+    ratio: f64,
+    step: f64,
+    chunk_size: usize,
+    /// Read position relative to `last` (index 0 = `last`, 1 = first sample of the next chunk).
+    pos: f64,
+    last: Option<f32>,
+}
+
+impl LinearResampler {
+    pub fn new(ratio: f64, chunk_size: usize) -> Self {
+        Self { ratio, step: 1.0 / ratio, chunk_size, pos: 1.0, last: None }
+    }
+
+    /// Scales the ratio given at construction by `rel`.
+    pub fn set_ratio_relative(&mut self, rel: f64) {
+        self.step = 1.0 / (self.ratio * rel);
+    }
+
+    pub fn process(&mut self, input: &[f32]) -> Vec<f32> {
+        if input.is_empty() {
+            return Vec::new();
+        }
+        // Prime with the first sample so the stream starts without a ramp from zero.
+        let last = self.last.unwrap_or(input[0]);
+        let sample = |i: usize| if i == 0 { last } else { input[i - 1] };
+
+        let n = input.len() as f64;
+        let mut out = Vec::with_capacity((n / self.step) as usize + 1);
+        while self.pos < n {
+            let idx = self.pos as usize;
+            let frac = (self.pos - idx as f64) as f32;
+            let a = sample(idx);
+            let b = sample(idx + 1);
+            out.push(a + (b - a) * frac);
+            self.pos += self.step;
+        }
+        self.pos -= n;
+        self.last = Some(input[input.len() - 1]);
+        out
+    }
+}
+
+/// Resamples interleaved audio with any number of channels.
+pub enum AudioResampler {
+    Sinc(SincFixedIn<f32>),
+    /// One resampler per channel; they share the ratio so outputs stay aligned.
+    Linear(Vec<LinearResampler>),
+}
+
+impl AudioResampler {
+    pub fn new(from_sr: f64, to_sr: f64, quality: ResamplerQuality, channels: usize) -> Self {
+        let ratio = to_sr / from_sr;
+        let quality = if quality == ResamplerQuality::Fast && ratio < LINEAR_MIN_RATIO {
+            ResamplerQuality::High
+        } else {
+            quality
+        };
+        match quality {
+            ResamplerQuality::High => {
+                let params = SincInterpolationParameters { sinc_len: 256, f_cutoff: 0.95, interpolation: SincInterpolationType::Linear, window: WindowFunction::BlackmanHarris2, oversampling_factor: 256 };
+                AudioResampler::Sinc(SincFixedIn::<f32>::new(ratio, 2.0, params, FRAME_SIZE, channels).unwrap())
+            }
+            ResamplerQuality::Fast => AudioResampler::Linear((0..channels).map(|_| LinearResampler::new(ratio, FRAME_SIZE)).collect()),
+        }
+    }
+
+    pub fn channels(&self) -> usize {
+        match self {
+            AudioResampler::Sinc(r) => r.nbr_channels(),
+            AudioResampler::Linear(rs) => rs.len(),
+        }
+    }
+
+    /// Input frames (samples per channel) the next `process` call expects.
+    pub fn input_frames_next(&self) -> usize {
+        match self {
+            AudioResampler::Sinc(r) => r.input_frames_next(),
+            AudioResampler::Linear(rs) => rs[0].chunk_size,
+        }
+    }
+
+    /// Nudges the conversion ratio relative to the nominal one (used for clock-drift
+    /// compensation; must stay well within the sinc resampler's 2.0 headroom).
+    pub fn set_ratio_relative(&mut self, rel: f64) {
+        match self {
+            AudioResampler::Sinc(r) => {
+                let _ = r.set_resample_ratio_relative(rel, true);
+            }
+            AudioResampler::Linear(rs) => rs.iter_mut().for_each(|r| r.set_ratio_relative(rel)),
+        }
+    }
+
+    /// Resamples exactly `input_frames_next()` interleaved frames.
+    pub fn process(&mut self, input: &[f32]) -> Option<Vec<f32>> {
+        let channels = self.channels();
+        if channels == 1 {
+            return match self {
+                AudioResampler::Sinc(r) => r.process(&[input], None).ok().map(|mut out| out.swap_remove(0)),
+                AudioResampler::Linear(rs) => Some(rs[0].process(input)),
+            };
+        }
+
+        let planar: Vec<Vec<f32>> = (0..channels).map(|c| input.iter().skip(c).step_by(channels).copied().collect()).collect();
+        let out = match self {
+            AudioResampler::Sinc(r) => r.process(&planar, None).ok()?,
+            AudioResampler::Linear(rs) => rs.iter_mut().zip(&planar).map(|(r, p)| r.process(p)).collect(),
+        };
+        let frames = out.iter().map(Vec::len).min().unwrap_or(0);
+        Some((0..frames).flat_map(|i| out.iter().map(move |ch| ch[i])).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    fn sine(freq: f64, sr: f64, len: usize) -> Vec<f32> {
+        (0..len).map(|i| (2.0 * std::f64::consts::PI * freq * i as f64 / sr).sin() as f32).collect()
+    }
+
+    #[test]
+    fn linear_preserves_dc_exactly() {
+        let mut r = LinearResampler::new(48000.0 / 44100.0, FRAME_SIZE);
+        for _ in 0..20 {
+            let out = r.process(&[0.25; FRAME_SIZE]);
+            assert!(out.iter().all(|&s| s == 0.25));
+        }
+    }
+
+    #[test]
+    fn linear_output_length_tracks_ratio() {
+        let mut r = LinearResampler::new(48000.0 / 44100.0, FRAME_SIZE);
+        let produced: usize = (0..441).map(|_| r.process(&[0.0; FRAME_SIZE]).len()).sum();
+        // 441 chunks of 480 at 44.1k is 4.8s, i.e. 230400 samples at 48k.
+        assert!((produced as i64 - 230_400).abs() <= 1, "{}", produced);
+    }
+
+    #[test]
+    fn linear_keeps_a_voice_band_tone() {
+        let input = sine(1000.0, 44100.0, FRAME_SIZE * 100);
+        let mut r = LinearResampler::new(48000.0 / 44100.0, FRAME_SIZE);
+        let out: Vec<f32> = input.chunks(FRAME_SIZE).flat_map(|c| r.process(c)).collect();
+        let expected = sine(1000.0, 48000.0, out.len());
+        let max_err = out.iter().zip(&expected).skip(1).map(|(a, b)| (a - b).abs()).fold(0.0f32, f32::max);
+        assert!(max_err < 0.01, "max error {}", max_err);
+    }
+
+    #[test]
+    fn fast_falls_back_to_sinc_for_large_downsampling() {
+        assert!(matches!(AudioResampler::new(96000.0, 48000.0, ResamplerQuality::Fast, 1), AudioResampler::Sinc(_)));
+        assert!(matches!(AudioResampler::new(44100.0, 48000.0, ResamplerQuality::Fast, 1), AudioResampler::Linear(_)));
+        assert!(matches!(AudioResampler::new(48000.0, 44100.0, ResamplerQuality::Fast, 1), AudioResampler::Linear(_)));
+    }
+
+    /// CPU comparison at 44.1k -> 48k; run with `cargo test --release -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_linear_vs_sinc() {
+        let input = sine(440.0, 44100.0, FRAME_SIZE);
+        for quality in [ResamplerQuality::High, ResamplerQuality::Fast] {
+            let mut r = AudioResampler::new(44100.0, 48000.0, quality, 1);
+            let start = Instant::now();
+            // 60s of audio.
+            for _ in 0..(44100 * 60 / FRAME_SIZE) {
+                std::hint::black_box(r.process(&input));
+            }
+            println!("{:?}: {:?} per minute of audio", quality, start.elapsed());
+        }
+    }
+}
@@ -0,0 +1,409 @@
+use notify::{RecursiveMode, Watcher};
+use parking_lot::Mutex;
+use serde_json::Value;
+use std::path::PathBuf;
+use std::sync::{mpsc, Arc};
+use std::time::Duration;
+use webrtc_audio_processing::{EchoCancellationSuppressionLevel, NoiseSuppressionLevel};
+
+use crate::{parse_key, AudioSettings, DeviceExclusivity, DspProfile, EqSettings, PttMode, ResamplerQuality};
+
+/// Why a settings file edit was rejected. The previous settings stay in effect.
+#[derive(Debug)]
+pub enum SettingsFileError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    NotAnObject,
+    UnknownKey(String),
+    InvalidValue { key: String, expected: &'static str },
+}
+
+impl std::fmt::Display for SettingsFileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SettingsFileError::Io(e) => write!(f, "Cannot read settings file: {}", e),
+            SettingsFileError::Json(e) => write!(f, "Settings file is not valid JSON: {}", e),
+            SettingsFileError::NotAnObject => write!(f, "Settings file must contain a JSON object"),
+            SettingsFileError::UnknownKey(key) => write!(f, "Unknown setting '{}'", key),
+            SettingsFileError::InvalidValue { key, expected } => write!(f, "Setting '{}' must be {}", key, expected),
+        }
+    }
+}
+
+impl std::error::Error for SettingsFileError {}
+
+/// What an applied reload changed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SettingsReload {
+    /// Only settings an open session picks up live (see `AudioSession::update_settings`).
+    Live,
+    /// A device, format or processing-layout setting changed; the session must be reopened.
+    Restart,
+}
+
+/// Whether going from `old` to `new` needs the session reopened. Everything not
+/// compared here is applied live.
+pub fn reload_kind(old: &AudioSettings, new: &AudioSettings) -> SettingsReload {
+    let restart = old.input_device_id != new.input_device_id
+        || old.channels != new.channels
+        || old.output_channel_map != new.output_channel_map
+        || old.exclusivity != new.exclusivity
+        || old.resampler_quality != new.resampler_quality
+        || old.dsp_profile != new.dsp_profile
+        || old.enable_extra_denoise != new.enable_extra_denoise
+        || old.output_target_fill_ms != new.output_target_fill_ms
+        || old.idle_timeout != new.idle_timeout;
+    if restart { SettingsReload::Restart } else { SettingsReload::Live }
+}
+
+/// Watches a JSON settings file and applies it to `settings` whenever it
+/// changes (and once at start if it exists). Only the keys present in the file
+/// are touched. A read or parse error is logged and the previous settings are
+/// kept. The receiver gets one message per applied reload saying whether the
+/// caller's session must be reopened; the watcher exits on the first reload
+/// after the receiver is dropped.
+///
+/// The file's directory is watched rather than the file itself, since editors
+/// usually save by replacing the file.
+pub fn watch_settings_file(
+    path: impl Into<PathBuf>,
+    settings: Arc<Mutex<AudioSettings>>,
+) -> notify::Result<mpsc::Receiver<SettingsReload>> {
+    let path = path.into();
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let (events_tx, events) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(events_tx)?;
+    watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+    let (tx, rx) = mpsc::channel();
+
+    std::thread::spawn(move || {
+        let _watcher = watcher;
+        // One save fires several events; reloading only changed text applies it once.
+        let mut last_text: Option<String> = None;
+        let mut reload = || -> bool {
+            let text = match std::fs::read_to_string(&path) {
+                Ok(text) if last_text.as_ref() != Some(&text) => text,
+                Ok(_) => return true,
+                // Gone (e.g. mid-replace); the next event picks up the new file.
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => return true,
+                Err(e) => {
+                    println!("⚠️ Ignoring {}: {}", path.display(), SettingsFileError::Io(e));
+                    return true;
+                }
+            };
+            let kind = {
+                let mut current = settings.lock();
+                match apply_settings_json(&current, &text) {
+                    Ok(updated) => {
+                        let kind = reload_kind(&current, &updated);
+                        *current = updated;
+                        Ok(kind)
+                    }
+                    Err(e) => Err(e),
+                }
+            };
+            last_text = Some(text);
+            match kind {
+                Ok(kind) => {
+                    println!("🔄 Settings reloaded from {}", path.display());
+                    tx.send(kind).is_ok()
+                }
+                Err(e) => {
+                    println!("⚠️ Ignoring {}: {}", path.display(), e);
+                    true
+                }
+            }
+        };
+
+        if !reload() {
+            return;
+        }
+        for event in events {
+            let ours = match event {
+                Ok(event) => event.paths.iter().any(|p| p.file_name() == path.file_name()),
+                Err(e) => {
+                    println!("⚠️ Settings file watch error: {}", e);
+                    false
+                }
+            };
+            if ours && !reload() {
+                return;
+            }
+        }
+    });
+
+    Ok(rx)
+}
+
+/// Returns `base` with every key of the JSON object `text` applied. Unknown keys
+/// are rejected so a typo does not silently do nothing.
+pub fn apply_settings_json(base: &AudioSettings, text: &str) -> Result<AudioSettings, SettingsFileError> {
+    let value: Value = serde_json::from_str(text).map_err(SettingsFileError::Json)?;
+    let Value::Object(map) = value else {
+        return Err(SettingsFileError::NotAnObject);
+    };
+
+    let mut s = base.clone();
+    for (key, value) in &map {
+        let invalid = |expected| SettingsFileError::InvalidValue { key: key.clone(), expected };
+        match key.as_str() {
+            "input_device_id" => s.input_device_id = value.as_str().ok_or_else(|| invalid("a string"))?.to_string(),
+            "ptt_key" => {
+                s.ptt_key = value.as_str().and_then(parse_key).ok_or_else(|| invalid("a key name such as \"ControlLeft\" or \"KeyV\""))?
+            }
+            "ptt_enabled" => s.ptt_enabled = value.as_bool().ok_or_else(|| invalid("true or false"))?,
+            "ptt_mode" => {
+                s.ptt_mode = match value.as_str() {
+                    Some("hold") => PttMode::Hold,
+                    Some("toggle") => PttMode::Toggle,
+                    _ => return Err(invalid("\"hold\" or \"toggle\"")),
+                }
+            }
+            "ptt_match_both_modifiers" => s.ptt_match_both_modifiers = value.as_bool().ok_or_else(|| invalid("true or false"))?,
+            "aec_enabled" => s.aec_enabled = value.as_bool().ok_or_else(|| invalid("true or false"))?,
+            "aec_suppression_level" => {
+                s.aec_suppression_level = value
+                    .as_str()
+                    .and_then(suppression_level)
+                    .ok_or_else(|| invalid("\"low\", \"moderate\" or \"high\""))?
+            }
+            "aec_delay_agnostic" => s.aec_delay_agnostic = value.as_bool().ok_or_else(|| invalid("true or false"))?,
+            "aec_extended_filter" => s.aec_extended_filter = value.as_bool().ok_or_else(|| invalid("true or false"))?,
+            "aec_stream_delay_ms" => s.aec_stream_delay_ms = optional(value, u16_value).ok_or_else(|| invalid("milliseconds or null"))?,
+            "render_processing_enabled" => s.render_processing_enabled = value.as_bool().ok_or_else(|| invalid("true or false"))?,
+            "agc_enabled" => s.agc_enabled = value.as_bool().ok_or_else(|| invalid("true or false"))?,
+            "transient_suppressor_enabled" => {
+                s.transient_suppressor_enabled = value.as_bool().ok_or_else(|| invalid("true or false"))?
+            }
+            "transmit_prebuffer_ms" => s.transmit_prebuffer_ms = u16_value(value).ok_or_else(|| invalid("milliseconds"))?,
+            "resampler_quality" => {
+                s.resampler_quality = match value.as_str() {
+                    Some("high") => ResamplerQuality::High,
+                    Some("fast") => ResamplerQuality::Fast,
+                    _ => return Err(invalid("\"high\" or \"fast\"")),
+                }
+            }
+            "dsp_profile" => {
+                s.dsp_profile = match value.as_str() {
+                    Some("full") => DspProfile::Full,
+                    Some("rnnoise_only") => DspProfile::RnnoiseOnly,
+                    Some("none") => DspProfile::None,
+                    _ => return Err(invalid("\"full\", \"rnnoise_only\" or \"none\"")),
+                }
+            }
+            "channels" => s.channels = u16_value(value).filter(|c| (1..=2).contains(c)).ok_or_else(|| invalid("1 or 2"))?,
+            "output_channel_map" => {
+                s.output_channel_map = optional(value, |v| {
+                    v.as_array()?.iter().map(|c| c.as_u64().map(|c| c as usize)).collect()
+                })
+                .ok_or_else(|| invalid("an array of channel indices or null"))?
+            }
+            "exclusivity" => {
+                s.exclusivity = match value.as_str() {
+                    Some("shared") => DeviceExclusivity::Shared,
+                    Some("exclusive") => DeviceExclusivity::Exclusive,
+                    _ => return Err(invalid("\"shared\" or \"exclusive\"")),
+                }
+            }
+            "noise_suppression_level" => {
+                s.noise_suppression_level = match value.as_str() {
+                    Some("low") => NoiseSuppressionLevel::Low,
+                    Some("moderate") => NoiseSuppressionLevel::Moderate,
+                    Some("high") => NoiseSuppressionLevel::High,
+                    Some("very_high") => NoiseSuppressionLevel::VeryHigh,
+                    _ => return Err(invalid("\"low\", \"moderate\", \"high\" or \"very_high\"")),
+                }
+            }
+            "enable_extra_denoise" => s.enable_extra_denoise = value.as_bool().ok_or_else(|| invalid("true or false"))?,
+            "input_eq" => {
+                s.input_eq = optional(value, eq_value)
+                    .ok_or_else(|| invalid("an EQ object with frequencies between 0 and 24000Hz, gains within ±24dB and a positive Q, or null"))?
+            }
+            "output_loudness_target_lufs" => {
+                s.output_loudness_target_lufs = optional(value, |v| v.as_f64().map(|v| v as f32)).ok_or_else(|| invalid("LUFS or null"))?
+            }
+            "output_target_fill_ms" => s.output_target_fill_ms = optional(value, u16_value).ok_or_else(|| invalid("milliseconds or null"))?,
+            "idle_timeout_secs" => {
+                s.idle_timeout = optional(value, |v| v.as_f64().filter(|v| *v >= 0.0).and_then(|v| Duration::try_from_secs_f64(v).ok()))
+                    .ok_or_else(|| invalid("seconds or null"))?
+            }
+            _ => return Err(SettingsFileError::UnknownKey(key.clone())),
+        }
+    }
+    Ok(s)
+}
+
+fn suppression_level(name: &str) -> Option<EchoCancellationSuppressionLevel> {
+    match name {
+        "low" => Some(EchoCancellationSuppressionLevel::Low),
+        "moderate" => Some(EchoCancellationSuppressionLevel::Moderate),
+        "high" => Some(EchoCancellationSuppressionLevel::High),
+        _ => None,
+    }
+}
+
+fn u16_value(value: &Value) -> Option<u16> {
+    value.as_u64().and_then(|v| u16::try_from(v).ok())
+}
+
+/// An `EqSettings` object; bands left out keep their defaults.
+fn eq_value(value: &Value) -> Option<EqSettings> {
+    let mut eq = EqSettings::default();
+    for (key, v) in value.as_object()? {
+        let v = v.as_f64()? as f32;
+        match key.as_str() {
+            "low_freq_hz" => eq.low_freq_hz = v,
+            "low_gain_db" => eq.low_gain_db = v,
+            "mid_freq_hz" => eq.mid_freq_hz = v,
+            "mid_gain_db" => eq.mid_gain_db = v,
+            "mid_q" => eq.mid_q = v,
+            "high_freq_hz" => eq.high_freq_hz = v,
+            "high_gain_db" => eq.high_gain_db = v,
+            _ => return None,
+        }
+    }
+    eq.validate().ok().map(|_| eq)
+}
+
+/// `null` maps to `Some(None)`; anything else goes through `parse`.
+fn optional<T>(value: &Value, parse: impl Fn(&Value) -> Option<T>) -> Option<Option<T>> {
+    match value {
+        Value::Null => Some(None),
+        v => parse(v).map(Some),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DspChain;
+
+    fn apply(json: &str) -> Result<AudioSettings, SettingsFileError> {
+        apply_settings_json(&AudioSettings::default(), json)
+    }
+
+    #[test]
+    fn only_keys_in_the_file_are_changed() {
+        let s = apply(r#"{"ptt_key": "keyv", "ptt_mode": "toggle", "agc_enabled": false}"#).unwrap();
+        assert_eq!(s.ptt_key, rdev::Key::KeyV);
+        assert_eq!(s.ptt_mode, PttMode::Toggle);
+        assert!(!s.agc_enabled);
+        let defaults = AudioSettings::default();
+        assert_eq!(s.input_device_id, defaults.input_device_id);
+        assert_eq!(s.aec_enabled, defaults.aec_enabled);
+        assert_eq!(s.channels, defaults.channels);
+    }
+
+    #[test]
+    fn enum_and_numeric_values_are_parsed() {
+        let s = apply(
+            r#"{"resampler_quality": "fast", "dsp_profile": "rnnoise_only", "channels": 2,
+                "noise_suppression_level": "low", "exclusivity": "shared", "transmit_prebuffer_ms": 40,
+                "output_channel_map": [0, 1], "idle_timeout_secs": 1.5}"#,
+        )
+        .unwrap();
+        assert_eq!(s.resampler_quality, ResamplerQuality::Fast);
+        assert_eq!(s.dsp_profile, DspProfile::RnnoiseOnly);
+        assert_eq!(s.channels, 2);
+        assert_eq!(s.noise_suppression_level, NoiseSuppressionLevel::Low);
+        assert_eq!(s.exclusivity, DeviceExclusivity::Shared);
+        assert_eq!(s.transmit_prebuffer_ms, 40);
+        assert_eq!(s.output_channel_map, Some(vec![0, 1]));
+        assert_eq!(s.idle_timeout, Some(Duration::from_millis(1500)));
+    }
+
+    #[test]
+    fn out_of_range_idle_timeout_is_rejected() {
+        assert!(matches!(apply(r#"{"idle_timeout_secs": 1e300}"#), Err(SettingsFileError::InvalidValue { .. })));
+        assert!(matches!(apply(r#"{"idle_timeout_secs": -1}"#), Err(SettingsFileError::InvalidValue { .. })));
+    }
+
+    #[test]
+    fn null_clears_optional_settings() {
+        let base = AudioSettings { aec_stream_delay_ms: Some(40), output_target_fill_ms: Some(20), ..AudioSettings::default() };
+        let s = apply_settings_json(&base, r#"{"aec_stream_delay_ms": null, "output_target_fill_ms": null, "input_eq": null}"#).unwrap();
+        assert_eq!(s.aec_stream_delay_ms, None);
+        assert_eq!(s.output_target_fill_ms, None);
+        assert_eq!(s.input_eq, None);
+    }
+
+    #[test]
+    fn eq_object_keeps_defaults_for_missing_bands() {
+        let s = apply(r#"{"input_eq": {"low_gain_db": -6, "mid_q": 2}}"#).unwrap();
+        let eq = s.input_eq.unwrap();
+        assert_eq!(eq.low_gain_db, -6.0);
+        assert_eq!(eq.mid_q, 2.0);
+        assert_eq!(eq.high_freq_hz, EqSettings::default().high_freq_hz);
+        assert!(apply(r#"{"input_eq": {"treble": 3}}"#).is_err());
+        assert!(apply(r#"{"input_eq": {"mid_q": 0}}"#).is_err());
+        assert!(apply(r#"{"input_eq": {"high_freq_hz": 30000}}"#).is_err());
+    }
+
+    #[test]
+    fn invalid_files_are_rejected() {
+        assert!(matches!(apply("{"), Err(SettingsFileError::Json(_))));
+        assert!(matches!(apply("[1, 2]"), Err(SettingsFileError::NotAnObject)));
+        assert!(matches!(apply(r#"{"ptt_kee": "KeyV"}"#), Err(SettingsFileError::UnknownKey(k)) if k == "ptt_kee"));
+        for json in [
+            r#"{"channels": 3}"#,
+            r#"{"ptt_key": "NoSuchKey"}"#,
+            r#"{"ptt_enabled": "yes"}"#,
+            r#"{"transmit_prebuffer_ms": 70000}"#,
+            r#"{"idle_timeout_secs": -1}"#,
+            r#"{"dsp_profile": "max"}"#,
+        ] {
+            assert!(matches!(apply(json), Err(SettingsFileError::InvalidValue { .. })), "{}", json);
+        }
+    }
+
+    #[test]
+    fn only_device_and_layout_changes_need_a_restart() {
+        let base = AudioSettings::default();
+        for json in [r#"{"agc_enabled": false}"#, r#"{"noise_suppression_level": "low"}"#, r#"{"transmit_prebuffer_ms": 40}"#,
+            r#"{"output_loudness_target_lufs": -16}"#, r#"{"ptt_key": "KeyV"}"#]
+        {
+            assert_eq!(reload_kind(&base, &apply(json).unwrap()), SettingsReload::Live, "{}", json);
+        }
+        for json in [r#"{"input_device_id": "hw:1"}"#, r#"{"channels": 2}"#, r#"{"dsp_profile": "none"}"#,
+            r#"{"resampler_quality": "fast"}"#]
+        {
+            assert_eq!(reload_kind(&base, &apply(json).unwrap()), SettingsReload::Restart, "{}", json);
+        }
+    }
+
+    #[test]
+    fn file_changes_are_applied() {
+        let dir = std::env::temp_dir().join(format!("neandertal-settings-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("settings.json");
+        std::fs::write(&path, r#"{"agc_enabled": false}"#).unwrap();
+
+        let settings = Arc::new(Mutex::new(AudioSettings::default()));
+        let reloads = watch_settings_file(&path, settings.clone()).unwrap();
+        assert_eq!(reloads.recv_timeout(Duration::from_secs(5)), Ok(SettingsReload::Live));
+        assert!(!settings.lock().agc_enabled);
+
+        std::fs::write(&path, r#"{"agc_enabled": false, "channels": 2}"#).unwrap();
+        assert_eq!(reloads.recv_timeout(Duration::from_secs(5)), Ok(SettingsReload::Restart));
+        assert_eq!(settings.lock().channels, 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn aec_suppression_levels_reach_the_processor_config() {
+        for (name, level) in [
+            ("low", EchoCancellationSuppressionLevel::Low),
+            ("moderate", EchoCancellationSuppressionLevel::Moderate),
+            ("high", EchoCancellationSuppressionLevel::High),
+        ] {
+            let json = format!(r#"{{"aec_suppression_level": "{}"}}"#, name);
+            let settings = apply_settings_json(&AudioSettings::default(), &json).unwrap();
+            let aec = DspChain::processor_config(&settings).echo_cancellation.unwrap();
+            assert_eq!(aec.suppression_level, level, "{}", name);
+        }
+        assert!(apply_settings_json(&AudioSettings::default(), r#"{"aec_suppression_level": "max"}"#).is_err());
+    }
+}